@@ -1,5 +1,4 @@
-use crate::{cmd::print_json, Device, Result};
-use helium_crypto::{Keypair, Sign};
+use crate::{cmd::print_json, device::Key, Device, Result};
 use rand::{rngs::OsRng, RngCore};
 use serde_json::json;
 use std::time::{Duration, Instant};
@@ -18,8 +17,8 @@ pub struct Cmd {
 impl Cmd {
     pub fn run(&self, device: &Device) -> Result {
         device.init()?;
-        let keypair = device.get_keypair(false)?;
-        let duration = bench_sign(&keypair, self.iterations)?;
+        let key = device.get_keypair(false)?;
+        let duration = bench_sign(&key, self.iterations)?;
         let rate = self.iterations as f64 / duration.as_secs_f64();
         let avg_ms = duration.as_millis() as f64 / self.iterations as f64;
         let json = json!({
@@ -35,14 +34,14 @@ fn round2(v: f64) -> f64 {
     (v * 100.0).round() / 100.0
 }
 
-fn bench_sign(keypair: &Keypair, iterations: u32) -> Result<Duration> {
+fn bench_sign(key: &Key, iterations: u32) -> Result<Duration> {
     let mut total_duration = Duration::new(0, 0);
     for _ in 0..iterations {
         let mut data = [0u8; 32];
         OsRng.try_fill_bytes(&mut data)?;
 
         let start = Instant::now();
-        let _signature = keypair.sign(&data)?;
+        let _signature = key.sign(&data)?;
         total_duration += start.elapsed();
     }
     Ok(total_duration)
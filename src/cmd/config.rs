@@ -1,13 +1,262 @@
-use crate::{cmd::print_json, Device, Result};
+use crate::{
+    bail,
+    cmd::{print_json, test::run_tests},
+    Device, Result,
+};
+use serde_json::{json, Value};
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
 
 /// Gets the security device configuration
 #[derive(Debug, clap::Args)]
-pub struct Cmd {}
+pub struct Cmd {
+    /// Read and decode the configuration of all slots, including their lock
+    /// status and a raw dump of the configuration zone
+    #[arg(long)]
+    pub all: bool,
+
+    /// Check that the device communicates using the configured parameters,
+    /// like the wake delay and command durations of an ecc config file passed
+    /// in the device url
+    #[arg(long, conflicts_with_all = ["snapshot", "compare"])]
+    pub check: bool,
+
+    /// Write a snapshot of the full device state to the given file for use as
+    /// a golden reference
+    #[arg(long, conflicts_with = "compare")]
+    pub snapshot: Option<PathBuf>,
+
+    /// Compare the device state against a golden snapshot file and report the
+    /// differences. Fails when the device differs from the snapshot.
+    #[arg(long)]
+    pub compare: Option<PathBuf>,
+
+    /// A snapshot path to leave out of the comparison, like "keys" or
+    /// "config.slots[3]". Can be repeated.
+    #[arg(long, requires = "compare")]
+    pub ignore: Vec<String>,
+
+    /// Also compare values which are expected to differ between units, like
+    /// serial numbers and keys
+    #[arg(long, requires = "compare")]
+    pub strict: bool,
+}
 
 impl Cmd {
     pub fn run(&self, device: &Device) -> Result {
         device.init()?;
-        let config = device.get_config()?;
+        if self.check {
+            return run_tests(&device.get_config_tests()?);
+        }
+        if let Some(path) = &self.snapshot {
+            let snapshot = device.get_snapshot()?;
+            fs::write(path, serde_json::to_string_pretty(&snapshot)?)?;
+            return print_json(&snapshot);
+        }
+        if let Some(path) = &self.compare {
+            return self.compare(device, path);
+        }
+        let config = if self.all {
+            device.get_config_all()?
+        } else {
+            device.get_config()?
+        };
         print_json(&config)
     }
+
+    fn compare(&self, device: &Device, path: &Path) -> Result {
+        let golden: Value = serde_json::from_str(&fs::read_to_string(path)?)?;
+        let found = serde_json::to_value(device.get_snapshot()?)?;
+        let mut differences = vec![];
+        diff("", &golden, &found, &self.ignored(device), &mut differences);
+        let json = json!({
+            "result": if differences.is_empty() { "pass" } else { "fail" },
+            "differences": differences,
+        });
+        print_json(&json)?;
+        if !differences.is_empty() {
+            bail!(
+                "device differs from {} in {} places",
+                path.display(),
+                differences.len()
+            );
+        }
+        Ok(())
+    }
+
+    /// Returns the snapshot paths left out of the comparison.
+    fn ignored(&self, device: &Device) -> Vec<String> {
+        let mut ignore = self.ignore.clone();
+        if !self.strict {
+            ignore.extend(device.get_snapshot_unit_paths());
+        }
+        ignore
+    }
+}
+
+/// Walks the expected and found values and collects a difference for every
+/// leaf that does not match. Hex strings of equal length are compared byte by
+/// byte so a config zone difference reports the offending offset.
+fn diff(path: &str, expected: &Value, found: &Value, ignore: &[String], out: &mut Vec<Value>) {
+    if is_ignored(path, ignore) || expected == found {
+        return;
+    }
+    match (expected, found) {
+        (Value::Object(expected), Value::Object(found)) => {
+            let mut keys: Vec<&String> = expected.keys().chain(found.keys()).collect();
+            keys.sort();
+            keys.dedup();
+            for key in keys {
+                let path = if path.is_empty() {
+                    key.to_string()
+                } else {
+                    format!("{path}.{key}")
+                };
+                diff(
+                    &path,
+                    expected.get(key).unwrap_or(&Value::Null),
+                    found.get(key).unwrap_or(&Value::Null),
+                    ignore,
+                    out,
+                );
+            }
+        }
+        (Value::Array(expected), Value::Array(found)) => {
+            for index in 0..expected.len().max(found.len()) {
+                diff(
+                    &format!("{path}[{index}]"),
+                    expected.get(index).unwrap_or(&Value::Null),
+                    found.get(index).unwrap_or(&Value::Null),
+                    ignore,
+                    out,
+                );
+            }
+        }
+        (Value::String(expected), Value::String(found))
+            if expected.len() == found.len() && is_hex(expected) && is_hex(found) =>
+        {
+            let bytes = expected
+                .as_bytes()
+                .chunks(2)
+                .zip(found.as_bytes().chunks(2));
+            for (offset, (expected, found)) in bytes.enumerate() {
+                let path = format!("{path}[{offset}]");
+                if expected != found && !is_ignored(&path, ignore) {
+                    out.push(json!({
+                        "path": path,
+                        "expected": String::from_utf8_lossy(expected),
+                        "found": String::from_utf8_lossy(found),
+                    }));
+                }
+            }
+        }
+        _ => out.push(json!({
+            "path": path,
+            "expected": expected,
+            "found": found,
+        })),
+    }
+}
+
+fn is_ignored(path: &str, ignore: &[String]) -> bool {
+    ignore.iter().any(|prefix| {
+        path.strip_prefix(prefix.as_str())
+            .is_some_and(|rest| rest.is_empty() || rest.starts_with(['.', '[']))
+    })
+}
+
+fn is_hex(s: &str) -> bool {
+    !s.is_empty() && s.len().is_multiple_of(2) && s.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd(ignore: &[&str], strict: bool) -> Cmd {
+        Cmd {
+            all: false,
+            check: false,
+            snapshot: None,
+            compare: Some(PathBuf::from("golden.json")),
+            ignore: ignore.iter().map(|path| path.to_string()).collect(),
+            strict,
+        }
+    }
+
+    fn differences(expected: &Value, found: &Value, ignore: &[String]) -> Vec<String> {
+        let mut out = vec![];
+        diff("", expected, found, ignore, &mut out);
+        out.iter()
+            .map(|difference| difference["path"].as_str().expect("path").to_string())
+            .collect()
+    }
+
+    #[test]
+    fn ignore() {
+        let expected = json!({
+            "config": { "slots": [1, 2, 3, 4], "locked": true },
+            "keys": [{ "slot": 0, "key": "a" }],
+        });
+        let found = json!({
+            "config": { "slots": [1, 2, 3, 5], "locked": false },
+            "keys": [{ "slot": 0, "key": "b" }],
+        });
+        assert_eq!(
+            differences(&expected, &found, &[]),
+            ["config.locked", "config.slots[3]", "keys[0].key"]
+        );
+        let device: Device = "file:///tmp/key.bin".parse().expect("file device");
+        let ignore = cmd(&["keys", "config.slots[3]"], true).ignored(&device);
+        assert_eq!(differences(&expected, &found, &ignore), ["config.locked"]);
+        // Only whole path segments are ignored
+        let ignore = cmd(&["config.lock", "key"], true).ignored(&device);
+        assert_eq!(differences(&expected, &found, &ignore).len(), 3);
+        // Keys differ between units, so they are only compared when strict
+        let ignore = cmd(&[], false).ignored(&device);
+        assert_eq!(
+            differences(&expected, &found, &ignore),
+            ["config.locked", "config.slots[3]"]
+        );
+    }
+
+    #[test]
+    fn hex_bytes() {
+        let expected = json!({ "config_zone": "0123aabb" });
+        let found = json!({ "config_zone": "0123aacc" });
+        assert_eq!(differences(&expected, &found, &[]), ["config_zone[3]"]);
+    }
+
+    #[cfg(feature = "ecc608")]
+    #[test]
+    fn serial_offsets() {
+        let device: Device = "ecc://i2c-1".parse().expect("ecc device");
+        let expected = json!({ "config": { "config_zone": "00".repeat(16) }, "keys": [] });
+        let mut zone = ["00"; 16];
+        // The serial number is in bytes 0..4 and 8..13, byte 4 is not
+        for offset in [0, 3, 4, 8, 12] {
+            zone[offset] = "ff";
+        }
+        let found = json!({ "config": { "config_zone": zone.concat() }, "keys": ["key"] });
+
+        let ignore = cmd(&[], false).ignored(&device);
+        assert_eq!(
+            differences(&expected, &found, &ignore),
+            ["config.config_zone[4]"]
+        );
+        let ignore = cmd(&[], true).ignored(&device);
+        assert_eq!(
+            differences(&expected, &found, &ignore),
+            [
+                "config.config_zone[0]",
+                "config.config_zone[3]",
+                "config.config_zone[4]",
+                "config.config_zone[8]",
+                "config.config_zone[12]",
+                "keys[0]",
+            ]
+        );
+    }
 }
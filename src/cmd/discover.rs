@@ -0,0 +1,52 @@
+use crate::{
+    anyhow,
+    cmd::print_json,
+    device::discover::{discover, ECC_ADDRESSES},
+    Result,
+};
+use std::path::PathBuf;
+
+/// Scans the i2c buses and tpm device nodes for security devices
+///
+/// Lists a candidate device url with the info and lock state of every ecc part
+/// that responds. Does not need a security device.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    /// The i2c bus address to probe, may be repeated [default: 0x60, 0x35,
+    /// 0x36, 0x58]
+    #[arg(long = "address", value_parser = parse_address)]
+    addresses: Vec<u16>,
+
+    /// Include the addresses that did not respond
+    #[arg(long)]
+    all: bool,
+
+    /// The root directory containing the `dev` directory to scan
+    #[arg(long, default_value = "/")]
+    root: PathBuf,
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result {
+        let addresses = if self.addresses.is_empty() {
+            ECC_ADDRESSES
+        } else {
+            &self.addresses
+        };
+        let discovery = discover(&self.root, addresses, self.all)?;
+        print_json(&discovery)
+    }
+}
+
+/// Parses a decimal or `0x` prefixed hex bus address.
+fn parse_address(s: &str) -> Result<u16> {
+    let address = match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16),
+        None => s.parse(),
+    }
+    .map_err(|_| anyhow!("invalid bus address \"{s}\""))?;
+    if address > 127 {
+        return Err(anyhow!("invalid bus address {s}, expected a 7 bit address"));
+    }
+    Ok(address)
+}
@@ -0,0 +1,15 @@
+use crate::{cmd::test::run_tests, device::doctor, Result};
+
+/// Checks the station environment for the given security device
+///
+/// Reports the tool version, whether the binary supports the device, and the
+/// device nodes, kernel modules and services the device needs. Does not fail
+/// when the device url is invalid, but reports it as a failed check.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {}
+
+impl Cmd {
+    pub fn run(&self, url: Result<String>) -> Result {
+        run_tests(&doctor::checks(url))
+    }
+}
@@ -1,7 +1,11 @@
-use crate::{cmd::print_json, Device, Result};
-use angry_purple_tiger::AnimalName;
-use helium_crypto::Keypair;
-use serde_json::json;
+use crate::{
+    cmd::print_json,
+    device::{GatewaySettings, Key, Overwrite},
+    label::{self, KeyLabel},
+    Device, Result,
+};
+use serde::Serialize;
+use std::{fs, path::PathBuf};
 
 /// Prints public key information from the security device
 #[derive(Debug, clap::Args)]
@@ -10,21 +14,127 @@ pub struct Cmd {
     /// existing private key on the security device.
     #[arg(long)]
     pub generate: bool,
+
+    /// Import the private key from the given key file path or file url instead
+    /// of generating one. The key file format is detected as for file devices.
+    #[arg(long, conflicts_with = "generate")]
+    pub import: Option<String>,
+
+    #[command(flatten)]
+    pub overwrite: OverwriteArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
+
+/// Options for replacing an existing key file
+#[derive(Debug, Clone, Copy, clap::Args)]
+pub struct OverwriteArgs {
+    /// Overwrite an existing key file, tpm, keyring or pkcs11 key. Existing
+    /// keys on these devices are never replaced without this option.
+    #[arg(long)]
+    pub force: bool,
+
+    /// Keep a timestamped copy of an existing key file before replacing it
+    #[arg(long)]
+    pub backup: bool,
+}
+
+/// Additional output options for a printed public key
+#[derive(Debug, clap::Args)]
+pub struct OutputArgs {
+    /// Render the public key as a QR code on stderr
+    #[arg(long)]
+    pub qr: bool,
+
+    /// Write a printable label with the public key, animal name and device
+    /// serial to the given file
+    #[arg(long)]
+    pub label: Option<PathBuf>,
+
+    /// The label format to use. Defaults to the format implied by the label
+    /// file extension.
+    #[arg(long, value_enum, requires = "label")]
+    pub label_format: Option<label::Format>,
+
+    /// Include the canonical device url in the output
+    #[arg(long)]
+    pub url: bool,
+
+    /// Write a gateway-rs settings snippet with the keypair url for the
+    /// device to the given file
+    #[arg(long)]
+    pub settings: Option<PathBuf>,
+}
+
+impl OutputArgs {
+    /// Checks that the requested output can be written for the device, so a
+    /// key is not created only to fail writing the output for it.
+    pub(crate) fn check(&self, device: &Device) -> Result {
+        if self.settings.is_some() {
+            device.get_gateway_keypair()?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Serialize)]
+struct KeyOutput {
+    #[serde(flatten)]
+    label: KeyLabel,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    url: Option<String>,
 }
 
 impl Cmd {
     pub fn run(&self, device: &Device) -> Result {
         device.init()?;
-        let keypair = device.get_keypair(self.generate)?;
-        print_keypair(&keypair)
+        let device = device.clone().with_overwrite(self.overwrite.into());
+        self.output.check(&device)?;
+        let key = match &self.import {
+            Some(source) => device.import_keypair(&Device::from_key_file(source)?)?,
+            None => device.get_keypair(self.generate)?,
+        };
+        print_keypair(&device, &key, &self.output)
+    }
+}
+
+impl From<OverwriteArgs> for Overwrite {
+    fn from(args: OverwriteArgs) -> Self {
+        Self {
+            force: args.force,
+            backup: args.backup,
+        }
     }
 }
 
-pub(crate) fn print_keypair(keypair: &Keypair) -> Result {
-    let public_key_str = keypair.public_key().to_string();
-    let json = json!({
-        "key": public_key_str,
-        "name": public_key_str.parse::<AnimalName>()?.to_string(),
-    });
+pub(crate) fn print_keypair(device: &Device, key: &Key, output: &OutputArgs) -> Result {
+    // Only look up the serial when it's needed for a label to avoid an extra
+    // round trip to the security device
+    let serial = match output.label {
+        Some(_) => device.get_info()?.serial(),
+        None => None,
+    };
+    let key_label = KeyLabel::new(key.public_key(), serial)?;
+    if output.qr {
+        eprintln!("{}", key_label.to_terminal_qr()?);
+    }
+    if let Some(path) = &output.label {
+        let format = match output.label_format {
+            Some(format) => format,
+            None => label::Format::from_path(path)?,
+        };
+        fs::write(path, key_label.render(format)?)?;
+    }
+    if let Some(path) = &output.settings {
+        let settings = GatewaySettings {
+            keypair: device.get_gateway_keypair()?,
+        };
+        fs::write(path, toml::to_string(&settings)?)?;
+    }
+    let json = KeyOutput {
+        label: key_label,
+        url: output.url.then(|| device.to_string()),
+    };
     print_json(&json)
 }
@@ -1,10 +1,15 @@
 pub mod bench;
 pub mod config;
+pub mod discover;
+pub mod doctor;
 pub mod generate;
 pub mod info;
 pub mod key;
 pub mod provision;
+pub mod status;
 pub mod test;
+pub mod url;
+pub mod util;
 
 pub fn print_json<T: ?Sized + serde::ser::Serialize>(value: &T) -> crate::Result {
     println!("{}", serde_json::to_string_pretty(value)?);
@@ -1,13 +1,24 @@
-use crate::{cmd::key::print_keypair, Device, Result};
+use crate::{
+    cmd::key::{print_keypair, OutputArgs, OverwriteArgs},
+    Device, Result,
+};
 
 /// Configures the security device for gateway/miner use.
 #[derive(Debug, clap::Args)]
-pub struct Cmd {}
+pub struct Cmd {
+    #[command(flatten)]
+    pub overwrite: OverwriteArgs,
+
+    #[command(flatten)]
+    pub output: OutputArgs,
+}
 
 impl Cmd {
     pub fn run(&self, device: &Device) -> Result {
         device.init()?;
-        let keypair = device.provision()?;
-        print_keypair(&keypair)
+        let device = device.clone().with_overwrite(self.overwrite.into());
+        self.output.check(&device)?;
+        let key = device.provision()?;
+        print_keypair(&device, &key, &self.output)
     }
 }
@@ -0,0 +1,41 @@
+use crate::{cmd::print_json, label::KeyLabel, Device, Result};
+use serde::Serialize;
+use serde_json::json;
+
+/// Gets the combined info, config, key and lock status of the security device
+#[derive(Debug, clap::Args)]
+pub struct Cmd {}
+
+impl Cmd {
+    pub fn run(&self, device: &Device) -> Result {
+        device.init()?;
+        let info = device.get_info();
+        let config = device.get_config();
+        let serial = info.as_ref().ok().and_then(|info| info.serial());
+        let keypair = device.load_keypair();
+        let key_type = keypair
+            .as_ref()
+            .ok()
+            .map(|keypair| keypair.public_key().key_type());
+        let key = keypair.and_then(|keypair| KeyLabel::new(keypair.public_key(), serial));
+        let locked = config.as_ref().ok().and_then(|config| config.locked());
+        let json = json!({
+            "device": device.to_string(),
+            "info": to_json(info),
+            "config": to_json(config),
+            "key": to_json(key),
+            "locked": locked,
+            "capabilities": device.get_capabilities(key_type),
+        });
+        print_json(&json)
+    }
+}
+
+/// Converts the result of a device operation to json, reporting errors in
+/// place so a failure in one part does not hide the rest of the status.
+fn to_json<T: Serialize>(result: Result<T>) -> serde_json::Value {
+    match result.and_then(|value| Ok(serde_json::to_value(value)?)) {
+        Ok(value) => value,
+        Err(err) => json!({ "error": format!("{err:?}") }),
+    }
+}
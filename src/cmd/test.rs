@@ -16,35 +16,39 @@ pub struct Cmd {}
 impl Cmd {
     pub fn run(&self, device: &Device) -> Result {
         device.init()?;
-        let tests = device.get_tests();
-        let results: Vec<(String, TestResult)> = tests
-            .iter()
-            .map(|test| (test.to_string(), test.run()))
-            .collect();
-        let passed = test_results_to_pass_fail(&results);
-        let json_results: Vec<(String, serde_json::Value)> = results
-            .into_iter()
-            .map(|(test, result)| {
-                let (out_name, out_json) = test_result_to_json(&result);
-                (
-                    test,
-                    json!({
-                        "result": test_result_to_pass_fail(&result),
-                        out_name: out_json,
-                    }),
-                )
-            })
-            .collect();
-        let result_map: HashMap<String, serde_json::Value> = HashMap::from_iter(json_results);
-        let json = json!({
-            "result": passed,
-            "tests": result_map,
-        });
-
-        print_json(&json)
+        run_tests(&device.get_tests())
     }
 }
 
+/// Runs the given tests and prints their results as a json table.
+pub(crate) fn run_tests(tests: &[test::Test]) -> Result {
+    let results: Vec<(String, TestResult)> = tests
+        .iter()
+        .map(|test| (test.to_string(), test.run()))
+        .collect();
+    let passed = test_results_to_pass_fail(&results);
+    let json_results: Vec<(String, serde_json::Value)> = results
+        .into_iter()
+        .map(|(test, result)| {
+            let (out_name, out_json) = test_result_to_json(&result);
+            (
+                test,
+                json!({
+                    "result": test_result_to_pass_fail(&result),
+                    out_name: out_json,
+                }),
+            )
+        })
+        .collect();
+    let result_map: HashMap<String, serde_json::Value> = HashMap::from_iter(json_results);
+    let json = json!({
+        "result": passed,
+        "tests": result_map,
+    });
+
+    print_json(&json)
+}
+
 fn test_result_to_pass_fail(result: &TestResult) -> String {
     result
         .as_ref()
@@ -55,7 +59,7 @@ fn test_result_to_pass_fail(result: &TestResult) -> String {
 fn test_results_to_pass_fail(results: &[(String, TestResult)]) -> &'static str {
     if results
         .iter()
-        .all(|(_, result)| result.as_ref().map_or(false, |outcome| outcome.passed()))
+        .all(|(_, result)| result.as_ref().is_ok_and(|outcome| outcome.passed()))
     {
         "pass"
     } else {
@@ -0,0 +1,75 @@
+use crate::{cmd::print_json, Device, Result};
+use serde_json::json;
+
+/// Prints the canonical url for the given security device.
+///
+/// All defaults, like the ecc bus address and slot, are made explicit. The
+/// device is not accessed.
+#[derive(Debug, clap::Args)]
+pub struct Cmd {}
+
+impl Cmd {
+    pub fn run(&self, device: &Device) -> Result {
+        let json = json!({
+            "url": device.to_string(),
+        });
+        print_json(&json)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Parses the url and checks that its canonical url parses to a device
+    /// with the same canonical url, returning the canonical url.
+    fn round_trip(url: &str) -> String {
+        let device: Device = url.parse().expect("device url");
+        let canonical = device.to_string();
+        let parsed: Device = canonical.parse().expect("canonical url");
+        assert_eq!(parsed.to_string(), canonical, "{url}");
+        canonical
+    }
+
+    #[test]
+    fn file() {
+        assert_eq!(round_trip("file:///tmp/key.bin"), "/tmp/key.bin");
+        assert_eq!(
+            round_trip("file:///tmp/my%20key.bin?format=pkcs8-pem"),
+            "/tmp/my key.bin?format=pkcs8-pem"
+        );
+    }
+
+    #[cfg(feature = "ecc608")]
+    #[test]
+    fn ecc() {
+        assert_eq!(round_trip("ecc://i2c-1"), "ecc://i2c-1:96?slot=0");
+        assert_eq!(round_trip("ecc://i2c-0:0?slot=15"), "ecc://i2c-0:0?slot=15");
+        assert_eq!(
+            round_trip("ecc://i2c-1:127?slot=2&write-key=/lab/write%20key.bin"),
+            "ecc://i2c-1:127?slot=2&write-key=%2Flab%2Fwrite+key.bin"
+        );
+    }
+
+    #[cfg(feature = "tpm")]
+    #[test]
+    fn tpm() {
+        assert_eq!(
+            round_trip("tpm://tpm/HS/SRK/miner"),
+            "tpm://tpm/HS/SRK/miner"
+        );
+        assert_eq!(
+            round_trip("tpm://tpm/HS/SRK/miner?handle=0x81000002&tcti=swtpm:port%3D2321"),
+            "tpm://tpm/HS/SRK/miner?handle=0x81000002&tcti=swtpm%3Aport%3D2321"
+        );
+    }
+
+    #[cfg(feature = "nova-tz")]
+    #[test]
+    fn nova_tz() {
+        assert_eq!(
+            round_trip("nova-tz://rsa/keys/miner.blob"),
+            "nova-tz://rsa/keys/miner.blob"
+        );
+    }
+}
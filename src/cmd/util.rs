@@ -0,0 +1,101 @@
+use crate::{bail, cmd::print_json, Result};
+use angry_purple_tiger::AnimalName;
+use bytes::Bytes;
+use helium_crypto::PublicKey;
+use serde_json::json;
+
+/// Offline public key utilities which do not need a security device
+#[derive(Debug, clap::Args)]
+pub struct Cmd {
+    #[command(subcommand)]
+    pub cmd: UtilCmd,
+}
+
+#[derive(Debug, clap::Subcommand)]
+pub enum UtilCmd {
+    Name(Name),
+    Decode(Decode),
+    Check(Check),
+}
+
+impl Cmd {
+    pub fn run(&self) -> Result {
+        match &self.cmd {
+            UtilCmd::Name(cmd) => cmd.run(),
+            UtilCmd::Decode(cmd) => cmd.run(),
+            UtilCmd::Check(cmd) => cmd.run(),
+        }
+    }
+}
+
+/// Prints the animal name for a given public key
+#[derive(Debug, clap::Args)]
+pub struct Name {
+    /// The public key to name
+    pub key: PublicKey,
+}
+
+impl Name {
+    pub fn run(&self) -> Result {
+        let json = json!({
+            "key": self.key.to_string(),
+            "name": animal_name(&self.key)?,
+        });
+        print_json(&json)
+    }
+}
+
+/// Decodes a public key into its key type, network and raw bytes
+#[derive(Debug, clap::Args)]
+pub struct Decode {
+    /// The public key to decode
+    pub key: PublicKey,
+}
+
+impl Decode {
+    pub fn run(&self) -> Result {
+        let key_tag = self.key.key_tag();
+        let json = json!({
+            "key": self.key.to_string(),
+            "name": animal_name(&self.key)?,
+            "type": key_tag.key_type.to_string(),
+            "network": key_tag.network.to_string(),
+            "bytes": format!("{:#02x}", Bytes::from(self.key.to_vec())),
+        });
+        print_json(&json)
+    }
+}
+
+/// Checks that a given string is a valid public key
+#[derive(Debug, clap::Args)]
+pub struct Check {
+    /// The public key to check
+    pub key: String,
+}
+
+impl Check {
+    /// Prints the check result, and fails when the key is invalid so scripts
+    /// can rely on the exit status.
+    pub fn run(&self) -> Result {
+        match self.key.parse::<PublicKey>() {
+            Ok(key) => print_json(&json!({
+                "key": self.key,
+                "result": "pass",
+                "type": key.key_tag().key_type.to_string(),
+                "network": key.key_tag().network.to_string(),
+            })),
+            Err(err) => {
+                print_json(&json!({
+                    "key": self.key,
+                    "result": "fail",
+                    "error": err.to_string(),
+                }))?;
+                bail!("invalid public key {}", self.key)
+            }
+        }
+    }
+}
+
+fn animal_name(key: &PublicKey) -> Result<String> {
+    Ok(key.to_string().parse::<AnimalName>()?.to_string())
+}
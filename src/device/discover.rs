@@ -0,0 +1,176 @@
+#[cfg(feature = "ecc608")]
+use crate::device::ecc;
+use crate::{anyhow, Result};
+use serde::Serialize;
+use std::{
+    fs,
+    path::{Path, PathBuf},
+};
+
+/// The i2c bus addresses ecc parts are commonly found at, in the order they
+/// are probed.
+pub const ECC_ADDRESSES: &[u16] = &[0x60, 0x35, 0x36, 0x58];
+
+/// The tpm device nodes checked by discovery. `tpmrm0` is the kernel
+/// resource manager and is preferred when present.
+const TPM_NODES: &[&str] = &["tpmrm0", "tpm0"];
+
+/// The security devices found on a system.
+#[derive(Debug, Serialize)]
+pub struct Discovery {
+    /// The i2c buses that were scanned
+    pub buses: Vec<String>,
+    #[cfg(feature = "ecc608")]
+    pub ecc: Vec<ecc::Discovered>,
+    pub tpm: Vec<TpmNode>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct TpmNode {
+    pub path: String,
+    pub resource_manager: bool,
+}
+
+/// Scans the `dev` directory under the given root for i2c buses and tpm
+/// device nodes, and probes each bus for ecc parts at the given addresses.
+/// Probes that did not find a part are only included when `all` is set.
+///
+/// The root is normally `/`, but can point at a directory with a mock `dev`
+/// tree to check discovery without hardware.
+pub fn discover(root: &Path, addresses: &[u16], all: bool) -> Result<Discovery> {
+    let dev = root.join("dev");
+    let buses = i2c_buses(&dev)?;
+
+    #[cfg(feature = "ecc608")]
+    let ecc = buses
+        .iter()
+        .flat_map(|bus| ecc::discover(bus, addresses))
+        .filter(|discovered| all || discovered.found())
+        .collect();
+    #[cfg(not(feature = "ecc608"))]
+    let _ = (addresses, all);
+
+    let tpm = TPM_NODES
+        .iter()
+        .map(|node| dev.join(node))
+        .filter(|path| path.exists())
+        .map(|path| TpmNode {
+            resource_manager: path.ends_with("tpmrm0"),
+            path: path.to_string_lossy().into_owned(),
+        })
+        .collect();
+
+    Ok(Discovery {
+        buses: buses
+            .iter()
+            .map(|bus| bus.to_string_lossy().into_owned())
+            .collect(),
+        #[cfg(feature = "ecc608")]
+        ecc,
+        tpm,
+    })
+}
+
+/// Returns the `i2c-<n>` device nodes in the given directory, ordered by bus
+/// number.
+fn i2c_buses(dev: &Path) -> Result<Vec<PathBuf>> {
+    let mut buses: Vec<(u32, PathBuf)> = fs::read_dir(dev)
+        .map_err(|err| anyhow!("failed to read {}: {err}", dev.display()))?
+        .filter_map(|entry| entry.ok())
+        .filter_map(|entry| {
+            let bus = entry
+                .file_name()
+                .to_str()?
+                .strip_prefix("i2c-")?
+                .parse()
+                .ok()?;
+            Some((bus, entry.path()))
+        })
+        .collect();
+    buses.sort();
+    Ok(buses.into_iter().map(|(_, path)| path).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// Builds a mock `dev` tree with the given device nodes as regular files.
+    fn mock_root(name: &str, nodes: &[&str]) -> PathBuf {
+        let root = env::temp_dir().join(format!("gateway_mfr-{name}-{}", process::id()));
+        let dev = root.join("dev");
+        fs::create_dir_all(&dev).expect("mock dev");
+        for node in nodes {
+            fs::write(dev.join(node), b"").expect("mock node");
+        }
+        root
+    }
+
+    #[test]
+    fn mock_bus_directory() {
+        let root = mock_root("discover", &["i2c-10", "i2c-1", "i2c-2", "tpmrm0", "null"]);
+        let discovery = discover(&root, &[0x60, 0x35], true);
+        fs::remove_dir_all(&root).expect("remove mock root");
+        let discovery = discovery.expect("discovery");
+
+        let dev = root.join("dev");
+        let node = |name: &str| dev.join(name).to_string_lossy().into_owned();
+        assert_eq!(
+            discovery.buses,
+            vec![node("i2c-1"), node("i2c-2"), node("i2c-10")]
+        );
+
+        assert_eq!(discovery.tpm.len(), 1);
+        assert_eq!(discovery.tpm[0].path, node("tpmrm0"));
+        assert!(discovery.tpm[0].resource_manager);
+
+        #[cfg(feature = "ecc608")]
+        {
+            // The mock nodes are not i2c buses, so every probe fails and is
+            // only reported because all probes were requested
+            let ecc = serde_json::to_value(&discovery.ecc).expect("ecc json");
+            let urls: Vec<&str> = ecc
+                .as_array()
+                .expect("ecc entries")
+                .iter()
+                .map(|entry| {
+                    assert!(entry["error"].is_string(), "{entry}");
+                    assert!(entry.get("info").is_none(), "{entry}");
+                    entry["url"].as_str().expect("url")
+                })
+                .collect();
+            assert_eq!(
+                urls,
+                vec![
+                    "ecc://i2c-1:96?slot=0",
+                    "ecc://i2c-1:53?slot=0",
+                    "ecc://i2c-2:96?slot=0",
+                    "ecc://i2c-2:53?slot=0",
+                    "ecc://i2c-10:96?slot=0",
+                    "ecc://i2c-10:53?slot=0",
+                ]
+            );
+            assert!(discovery.ecc.iter().all(|discovered| !discovered.found()));
+        }
+    }
+
+    #[test]
+    fn failed_probes_are_hidden() {
+        let root = mock_root("discover-found", &["i2c-1", "tpm0"]);
+        let discovery = discover(&root, ECC_ADDRESSES, false);
+        fs::remove_dir_all(&root).expect("remove mock root");
+        let discovery = discovery.expect("discovery");
+
+        #[cfg(feature = "ecc608")]
+        assert!(discovery.ecc.is_empty());
+        assert_eq!(discovery.tpm.len(), 1);
+        assert!(!discovery.tpm[0].resource_manager);
+    }
+
+    #[test]
+    fn missing_dev_directory() {
+        let root = env::temp_dir().join(format!("gateway_mfr-missing-{}", process::id()));
+        assert!(discover(&root, ECC_ADDRESSES, true).is_err());
+    }
+}
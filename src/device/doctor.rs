@@ -0,0 +1,290 @@
+use crate::{
+    anyhow,
+    device::{
+        file,
+        test::{self, TestResult},
+        Device,
+    },
+    Result,
+};
+use http::Uri;
+use std::{
+    fmt,
+    fs::{self, OpenOptions},
+    io::ErrorKind,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
+
+/// The device url schemes this binary was built with.
+const SCHEMES: &[&str] = &[
+    #[cfg(feature = "ecc608")]
+    "ecc",
+    #[cfg(feature = "tpm")]
+    "tpm",
+    #[cfg(feature = "nova-tz")]
+    "nova-tz",
+    #[cfg(feature = "keyring")]
+    "keyring",
+    #[cfg(feature = "pkcs11")]
+    "pkcs11",
+    "file",
+    "exec",
+];
+
+/// The sysfs class that exists when the i2c-dev module is loaded.
+const I2C_DEV_CLASS: &str = "/sys/class/i2c-dev";
+/// The kernel tpm resource manager device node.
+const TPM_RM_NODE: &str = "/dev/tpmrm0";
+/// The raw tpm device node.
+const TPM_NODE: &str = "/dev/tpm0";
+
+/// A check of the station environment for the requested device.
+pub enum Check {
+    /// The version of this tool
+    Version,
+    /// The device url to check, or why it could not be resolved
+    Device(Result<String>),
+    /// Whether the binary was built with support for the url scheme
+    Feature(String),
+    /// Whether the device node exists and can be opened for read and write
+    Node(PathBuf),
+    /// Whether the i2c-dev kernel module is loaded
+    I2cDev,
+    /// Whether a tpm resource manager is available
+    ResourceManager,
+    /// Whether the key file exists and can be read, or can be created
+    KeyFile(PathBuf),
+    /// Whether the exec helper exists and is executable
+    Helper(PathBuf),
+    /// Whether the pkcs11 module exists and can be read
+    Module(PathBuf),
+    /// Whether the device can be initialized
+    Init(String),
+}
+
+/// Returns the checks for the given device url.
+pub fn checks(url: Result<String>) -> Vec<test::Test> {
+    let resolved = url.as_ref().ok().cloned();
+    let mut checks = vec![Check::Version, Check::Device(url)];
+    if let Some(url) = &resolved {
+        let scheme = scheme(url);
+        checks.push(Check::Feature(scheme.to_string()));
+        match scheme {
+            "ecc" => {
+                if let Some(node) = ecc_node(url) {
+                    checks.push(Check::Node(node));
+                }
+                checks.push(Check::I2cDev);
+            }
+            // Device nodes only matter when the tcti goes through them
+            "tpm" => match tpm_tcti(url).as_deref() {
+                None => {
+                    checks.push(Check::ResourceManager);
+                    checks.push(Check::Node(tpm_node()));
+                }
+                Some("device") => checks.push(Check::Node(tpm_node())),
+                Some(tcti) => {
+                    if let Some(node) = tcti.strip_prefix("device:") {
+                        checks.push(Check::Node(PathBuf::from(node)));
+                    }
+                }
+            },
+            "file" => {
+                if let Ok(device) = file::Device::from_url(url) {
+                    checks.push(Check::KeyFile(device.path));
+                }
+            }
+            "nova-tz" => {
+                if let Ok(url) = url.parse::<Uri>() {
+                    checks.push(Check::KeyFile(PathBuf::from(url.path())));
+                }
+            }
+            "exec" => {
+                if let Some(path) = url.strip_prefix("exec://") {
+                    let path = path.split_once('?').map_or(path, |(path, _)| path);
+                    checks.push(Check::Helper(PathBuf::from(path)));
+                }
+            }
+            "pkcs11" => {
+                if let Some(path) = url.strip_prefix("pkcs11://") {
+                    let path = path.split_once('?').map_or(path, |(path, _)| path);
+                    checks.push(Check::Module(PathBuf::from(path)));
+                }
+            }
+            _ => (),
+        }
+        checks.push(Check::Init(url.clone()));
+    }
+    checks.into_iter().map(test::Test::Doctor).collect()
+}
+
+fn scheme(url: &str) -> &str {
+    if file::Device::is_file_url(url) {
+        return "file";
+    }
+    url.split_once("://").map_or("", |(scheme, _)| scheme)
+}
+
+fn ecc_node(url: &str) -> Option<PathBuf> {
+    let url: Uri = url.parse().ok()?;
+    url.host().map(|dev| Path::new("/dev").join(dev))
+}
+
+fn tpm_tcti(url: &str) -> Option<String> {
+    let url: Uri = url.parse().ok()?;
+    let args: Vec<(String, String)> = serde_urlencoded::from_str(url.query()?).ok()?;
+    args.into_iter()
+        .find_map(|(name, value)| (name == "tcti").then_some(value))
+}
+
+fn tpm_node() -> PathBuf {
+    if Path::new(TPM_RM_NODE).exists() {
+        PathBuf::from(TPM_RM_NODE)
+    } else {
+        PathBuf::from(TPM_NODE)
+    }
+}
+
+impl fmt::Display for Check {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Version => f.write_str("version"),
+            Self::Device(_) => f.write_str("device"),
+            Self::Feature(scheme) => f.write_fmt(format_args!("feature({scheme})")),
+            Self::Node(path) => f.write_fmt(format_args!("node({})", path.display())),
+            Self::I2cDev => f.write_str("i2c_dev"),
+            Self::ResourceManager => f.write_str("resource_manager"),
+            Self::KeyFile(path) => f.write_fmt(format_args!("key_file({})", path.display())),
+            Self::Helper(path) => f.write_fmt(format_args!("helper({})", path.display())),
+            Self::Module(path) => f.write_fmt(format_args!("module({})", path.display())),
+            Self::Init(_) => f.write_str("init"),
+        }
+    }
+}
+
+impl Check {
+    pub fn run(&self) -> TestResult {
+        match self {
+            Self::Version => test::pass(env!("CARGO_PKG_VERSION")).into(),
+            Self::Device(url) => match url {
+                Ok(url) => test::pass(url).into(),
+                Err(err) => test::fail(err).into(),
+            },
+            Self::Feature(scheme) => check_feature(scheme),
+            Self::Node(path) => check_node(path),
+            Self::I2cDev => check_i2c_dev(),
+            Self::ResourceManager => check_resource_manager(),
+            Self::KeyFile(path) => check_key_file(path),
+            Self::Helper(path) => check_helper(path),
+            Self::Module(path) => check_module(path),
+            Self::Init(url) => check_init(url),
+        }
+    }
+}
+
+fn check_feature(scheme: &str) -> TestResult {
+    if SCHEMES.contains(&scheme) {
+        return test::pass(scheme).into();
+    }
+    test::expected(format!("one of {}", SCHEMES.join(", ")), scheme.to_string()).into()
+}
+
+fn check_node(path: &Path) -> TestResult {
+    match OpenOptions::new().read(true).write(true).open(path) {
+        Ok(_) => test::pass("ok").into(),
+        Err(err) if err.kind() == ErrorKind::NotFound => test::fail("missing").into(),
+        Err(err) if err.kind() == ErrorKind::PermissionDenied => {
+            test::fail("permission denied, check the owner and group of the node").into()
+        }
+        Err(err) => test::fail(err).into(),
+    }
+}
+
+fn check_i2c_dev() -> TestResult {
+    if Path::new(I2C_DEV_CLASS).exists() {
+        return test::pass("loaded").into();
+    }
+    test::fail("not loaded, load it with \"modprobe i2c-dev\"").into()
+}
+
+fn check_resource_manager() -> TestResult {
+    if Path::new(TPM_RM_NODE).exists() {
+        return test::pass(TPM_RM_NODE).into();
+    }
+    test::fail(format!(
+        "{TPM_RM_NODE} not found, the kernel resource manager is not available"
+    ))
+    .into()
+}
+
+fn check_key_file(path: &Path) -> TestResult {
+    let Some(parent) = path.parent() else {
+        return test::fail("not a file path").into();
+    };
+    if path.exists() {
+        // provision replaces the key file, which needs both the file and its
+        // directory to be writable
+        if let Err(err) = OpenOptions::new().read(true).write(true).open(path) {
+            return test::fail(format!("{} is not writable: {err}", path.display())).into();
+        }
+        return match check_dir_writable(parent) {
+            Ok(()) => test::pass("ok").into(),
+            Err(err) => test::fail(format!("{} is not writable: {err}", parent.display())).into(),
+        };
+    }
+    // Missing parent directories are created under the nearest existing one
+    let Some(ancestor) = parent.ancestors().find(|dir| dir.exists()) else {
+        return test::fail("no existing parent directory").into();
+    };
+    if !ancestor.is_dir() {
+        return test::fail(format!("{} is not a directory", ancestor.display())).into();
+    }
+    if let Err(err) = check_dir_writable(ancestor) {
+        return test::fail(format!("{} is not writable: {err}", ancestor.display())).into();
+    }
+    if ancestor == parent {
+        test::pass("missing, will be created").into()
+    } else {
+        test::pass("missing, will be created with its parent directory").into()
+    }
+}
+
+/// Checks that files can be created in the given directory by creating and
+/// removing one, the same way key files are written.
+fn check_dir_writable(dir: &Path) -> std::io::Result<()> {
+    let probe = dir.join(format!(".gateway_mfr-doctor.{}.tmp", std::process::id()));
+    OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .open(&probe)?;
+    fs::remove_file(probe)
+}
+
+fn check_helper(path: &Path) -> TestResult {
+    match fs::metadata(path) {
+        Ok(metadata) if metadata.is_file() && metadata.permissions().mode() & 0o111 != 0 => {
+            test::pass("ok").into()
+        }
+        Ok(_) => test::fail("not an executable file").into(),
+        Err(err) if err.kind() == ErrorKind::NotFound => test::fail("missing").into(),
+        Err(err) => test::fail(err).into(),
+    }
+}
+
+fn check_module(path: &Path) -> TestResult {
+    match fs::File::open(path) {
+        Ok(file) if file.metadata()?.is_file() => test::pass("ok").into(),
+        Ok(_) => test::fail("not a file").into(),
+        Err(err) if err.kind() == ErrorKind::NotFound => test::fail("missing").into(),
+        Err(err) => test::fail(err).into(),
+    }
+}
+
+fn check_init(url: &str) -> TestResult {
+    let device: Device = url.parse()?;
+    device
+        .init()
+        .map_err(|err| anyhow!("failed to initialize {device}: {err}"))?;
+    test::pass("ok").into()
+}
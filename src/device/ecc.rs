@@ -1,25 +1,38 @@
 use crate::{
-    anyhow,
+    anyhow, bail,
     device::{
         test::{self, TestResult},
-        DeviceArgs,
+        DeviceArgs, Overwrite, SnapshotKey,
     },
     Result,
 };
-use bytes::Bytes;
+use bytes::{Bytes, BytesMut};
 use helium_crypto::{
-    ecc608::{self, key_config::KeyConfigType, with_ecc, Ecc, EccConfig},
+    ecc608::{
+        self,
+        address::{Address, DataBuffer},
+        key_config::KeyConfigType,
+        slot_config::{PrivWriteConfig, WriteCommand, WriteConfig},
+        with_ecc, Ecc, EccConfig,
+    },
     KeyTag, KeyType, Keypair, Network, Sign, Verify,
 };
 use http::Uri;
+use rand::{rngs::OsRng, RngCore};
 use serde::{Serialize, Serializer};
 use std::{
     fmt, fs,
     path::{Path, PathBuf},
+    time::Instant,
 };
 
 pub use ecc608::EccConfig as FileConfig;
 
+/// The default i2c bus address of the ecc
+const DEFAULT_ADDRESS: u16 = 96;
+/// The highest 7 bit i2c bus address
+const MAX_ADDRESS: u16 = 127;
+
 #[derive(Debug, Clone)]
 pub struct Device {
     /// The i2c/swi device path
@@ -30,26 +43,39 @@ pub struct Device {
     pub slot: u8,
     /// The config parameters
     pub config: Option<EccConfig>,
+    /// The file the config parameters were loaded from
+    pub config_path: Option<String>,
+    /// The file holding the write key of the slot, needed to import a key
+    /// once the data zone is locked
+    pub write_key: Option<PathBuf>,
+    /// Whether an imported key may replace the key in the slot
+    pub overwrite: Overwrite,
 }
 
 impl Device {
     /// Parses an ecc device url of the form `ecc:<dev>[:address][?slot=<slot>]`,
     /// where <dev> is the device file name (usually begins with i2c or tty),
     /// <address> is the bus address (default 96, ignored for swi), and <slot>
-    /// is the slot to use for key lookup/manipulation (default: 0)
+    /// is the slot to use for key lookup/manipulation (default: 0). A
+    /// `write-key` argument names the file with the 32 byte write key of the
+    /// slot for importing keys.
     pub fn from_url(url: &Uri) -> Result<Self> {
-        let args = DeviceArgs::from_uri(url)?;
-        let address = url.port_u16().unwrap_or(96);
-        let slot = args.get("slot", 0)?;
+        let args = DeviceArgs::from_uri(url, &["slot", "config", "write-key"])?;
+        let address = url.port_u16().unwrap_or(DEFAULT_ADDRESS);
+        if address > MAX_ADDRESS {
+            bail!(
+                "invalid ecc bus address {address}, expected a 7 bit address up to {MAX_ADDRESS}"
+            );
+        }
+        let slot = args.get_in_range("slot", 0, 0..=ecc608::MAX_SLOT)?;
         let path = url
             .host()
             .map(|dev| Path::new("/dev").join(dev))
             .ok_or_else(|| anyhow!("missing ecc device path"))?;
 
-        let config = if let Some(config_file) = args.get_string("config") {
-            let contents = fs::read_to_string(config_file)?;
-            let config: EccConfig = toml::from_str(&contents)?;
-            Some(config)
+        let config_path = args.get_string("config");
+        let config = if let Some(config_file) = &config_path {
+            Some(load_config(config_file)?)
         } else {
             None
         };
@@ -59,6 +85,9 @@ impl Device {
             address,
             slot,
             config,
+            config_path,
+            write_key: args.get_string("write-key").map(PathBuf::from),
+            overwrite: Overwrite::default(),
         })
     }
 
@@ -72,11 +101,11 @@ impl Device {
     }
 
     pub fn get_info(&self) -> Result<Info> {
-        let info = with_ecc(|ecc: &mut Ecc| {
+        let (info, serial) = with_ecc(|ecc: &mut Ecc| {
             ecc.get_info()
-                .and_then(|info| ecc.get_serial().map(|serial| Info { info, serial }))
+                .and_then(|info| ecc.get_serial().map(|serial| (info, serial)))
         })?;
-        Ok(info)
+        Ok(Info::new(info, serial))
     }
 
     pub fn get_keypair(&self, create: bool) -> Result<Keypair> {
@@ -103,6 +132,38 @@ impl Device {
         self.get_keypair(true)
     }
 
+    /// Returns the device url for the gateway-rs `keypair` setting. The
+    /// config and write key files are only used to provision the part, so
+    /// they are left out.
+    pub fn get_gateway_keypair(&self) -> String {
+        format!("{}?slot={}", self.bus_url(), self.slot)
+    }
+
+    fn bus_url(&self) -> String {
+        let dev = self.path.strip_prefix("/dev").unwrap_or(&self.path);
+        format!("ecc://{}:{}", dev.to_string_lossy(), self.address)
+    }
+
+    /// Writes the keypair into the slot with PrivWrite, refusing to replace
+    /// a key in the slot unless forced. Once the data zone is locked the slot
+    /// has to allow encrypted writes, and the key is encrypted with the slot's
+    /// write key.
+    pub fn import_keypair(&self, keypair: &Keypair) -> Result<Keypair> {
+        let private_key = import_private_key(keypair)?;
+        let mut num_in = [0u8; 20];
+        OsRng.fill_bytes(&mut num_in);
+        with_ecc(|ecc| {
+            import_key_in_slot(
+                ecc,
+                self.slot,
+                &private_key,
+                self.write_key.as_deref(),
+                self.overwrite.force,
+                &num_in,
+            )
+        })
+    }
+
     pub fn get_config(&self) -> Result<Config> {
         let slot_config = with_ecc(|ecc| ecc.get_slot_config(self.slot))?;
         let key_config = with_ecc(|ecc| ecc.get_key_config(self.slot))?;
@@ -114,16 +175,80 @@ impl Device {
             slot_config,
             key_config,
             zones,
+            slots: None,
+            config_zone: None,
+        })
+    }
+
+    /// Gets the configuration for the addressed slot along with the decoded
+    /// configuration and lock status of every slot and the raw config zone.
+    pub fn get_config_all(&self) -> Result<Config> {
+        let config_zone = with_ecc(read_config_zone)?;
+        let slots = (0..=ecc608::MAX_SLOT)
+            .map(|slot| {
+                let slot_config = with_ecc(|ecc| ecc.get_slot_config(slot))?;
+                let key_config = with_ecc(|ecc| ecc.get_key_config(slot))?;
+                Ok(SlotInfo {
+                    slot,
+                    locked: slot_locked(&config_zone, slot),
+                    slot_config,
+                    key_config,
+                })
+            })
+            .collect::<Result<Vec<SlotInfo>>>()?;
+        let config = self.get_config()?;
+        Ok(Config {
+            slots: Some(slots),
+            config_zone: Some(format!("{config_zone:#02x}")),
+            ..config
         })
     }
 
+    /// Reads the public key of every slot. Slots which do not hold a private
+    /// key report an empty key.
+    pub fn get_slot_keys(&self) -> Vec<SnapshotKey> {
+        (0..=ecc608::MAX_SLOT)
+            .map(|slot| SnapshotKey {
+                slot: Some(slot),
+                key: with_ecc(|ecc| compact_key_in_slot(ecc, slot))
+                    .ok()
+                    .map(|keypair| keypair.public_key().to_string()),
+            })
+            .collect()
+    }
+
+    pub fn get_snapshot_unit_paths(&self) -> Vec<String> {
+        SERIAL_OFFSETS
+            .into_iter()
+            .flatten()
+            .map(|offset| format!("config.config_zone[{offset}]"))
+            .chain(["keys".to_string()])
+            .collect()
+    }
+
     pub fn generate_config(&self) -> Result<FileConfig> {
         let config = ecc608::EccConfig::from_path(&self.path.to_string_lossy())?;
         Ok(config)
     }
 
+    /// Returns tests which exercise the non destructive chip commands to check
+    /// that the chip communicates using the configured wake delay and command
+    /// durations.
+    pub fn get_config_tests(&self) -> Vec<Test> {
+        vec![
+            Test::Command(Command::Info),
+            Test::Command(Command::Read),
+            Test::Command(Command::Random),
+            Test::Command(Command::Nonce),
+            Test::MinerKey(self.slot),
+            Test::Sign(self.slot),
+            Test::Ecdh(self.slot),
+        ]
+    }
+
     pub fn get_tests(&self) -> Vec<Test> {
         vec![
+            Test::Part,
             Test::zone_locked(ecc608::Zone::Data),
             Test::zone_locked(ecc608::Zone::Config),
             Test::slot_config(self.slot, ecc608::SlotConfig::default()),
@@ -135,11 +260,196 @@ impl Device {
     }
 }
 
+/// Loads an ecc config file as generated by the `generate` command and checks
+/// that the timing parameters are usable.
+fn load_config(path: &str) -> Result<EccConfig> {
+    let contents = fs::read_to_string(path)?;
+    let config: EccConfig =
+        toml::from_str(&contents).map_err(|err| anyhow!("invalid ecc config {path}: {err}"))?;
+    validate_config(&config).map_err(|err| anyhow!("invalid ecc config {path}: {err}"))?;
+    Ok(config)
+}
+
+/// Reads the 32 byte write key of a slot from a file.
+fn load_write_key(path: &Path) -> Result<[u8; 32]> {
+    fs::read(path)
+        .map_err(|err| anyhow!("failed to read write key {}: {err}", path.display()))?
+        .try_into()
+        .map_err(|_| anyhow!("write key {} must be 32 bytes", path.display()))
+}
+
+/// Upper bound for the wake delay and command durations in microseconds. The
+/// slowest commands take well under 100ms, so anything over a second is
+/// assumed to be a units mistake.
+const MAX_DURATION: u32 = 1_000_000;
+
+fn validate_config(config: &EccConfig) -> Result {
+    let durations = &config.durations;
+    let values = [
+        ("wake_delay", config.wake_delay),
+        ("info", durations.info),
+        ("read", durations.read),
+        ("write", durations.write),
+        ("lock", durations.lock),
+        ("nonce", durations.nonce),
+        ("random", durations.random),
+        ("genkey", durations.genkey),
+        ("sign", durations.sign),
+        ("ecdh", durations.ecdh),
+        ("gendig", durations.gendig),
+        ("priv_write", durations.priv_write),
+    ];
+    for (name, value) in values {
+        if value == 0 || value > MAX_DURATION {
+            bail!("{name} must be between 1 and {MAX_DURATION} microseconds, found {value}");
+        }
+    }
+    Ok(())
+}
+
+/// Renders the device url of the form `ecc://<dev>:<address>?slot=<slot>` with
+/// all defaults made explicit, which parses back to the same device.
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut args = vec![("slot", self.slot.to_string())];
+        if let Some(config_path) = &self.config_path {
+            args.push(("config", config_path.clone()));
+        }
+        if let Some(write_key) = &self.write_key {
+            args.push(("write-key", write_key.to_string_lossy().into_owned()));
+        }
+        let query = serde_urlencoded::to_string(args).map_err(|_| fmt::Error)?;
+        write!(f, "{}?{query}", self.bus_url())
+    }
+}
+
+/// An ecc part found on a bus during discovery.
+#[derive(Debug, Serialize)]
+pub struct Discovered {
+    url: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    info: Option<Info>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    locked: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    error: Option<String>,
+}
+
+impl Discovered {
+    pub fn found(&self) -> bool {
+        self.error.is_none()
+    }
+}
+
+/// Probes the given bus for an ecc part at each of the given addresses. The
+/// global ecc instance can only be initialized once, so each probe uses its
+/// own connection to the bus.
+pub fn discover(bus: &Path, addresses: &[u16]) -> Vec<Discovered> {
+    let dev = bus.file_name().map(Path::new).unwrap_or(bus);
+    addresses
+        .iter()
+        .map(|&address| {
+            let device = Device {
+                path: Path::new("/dev").join(dev),
+                address,
+                slot: 0,
+                config: None,
+                config_path: None,
+                write_key: None,
+                overwrite: Overwrite::default(),
+            };
+            let url = device.to_string();
+            match probe(bus, address) {
+                Ok((info, locked)) => Discovered {
+                    url,
+                    info: Some(info),
+                    locked: Some(locked),
+                    error: None,
+                },
+                Err(err) => Discovered {
+                    url,
+                    info: None,
+                    locked: None,
+                    error: Some(err.to_string()),
+                },
+            }
+        })
+        .collect()
+}
+
+fn probe(bus: &Path, address: u16) -> Result<(Info, bool)> {
+    let mut ecc = Ecc::from_path(&bus.to_string_lossy(), address, None)?;
+    let info = Info::new(ecc.get_info()?, ecc.get_serial()?);
+    let locked = ecc.get_locked(&ecc608::Zone::Config)? && ecc.get_locked(&ecc608::Zone::Data)?;
+    Ok((info, locked))
+}
+
 fn compact_key_in_slot(ecc: &mut Ecc, slot: u8) -> Result<Keypair> {
     let keypair = ecc608::Keypair::from_ecc_slot(ecc, Network::MainNet, slot)?;
     Ok(keypair.into())
 }
 
+/// Returns the private key of a keypair that can be imported into a slot.
+/// Slots hold mainnet ecc_compact keys, so anything else is rejected before
+/// the slot is written.
+fn import_private_key(keypair: &Keypair) -> Result<[u8; 32]> {
+    let key_tag = keypair.key_tag();
+    if key_tag.key_type != KeyType::EccCompact {
+        bail!(
+            "only ecc_compact keys can be imported into an ecc slot, not {}",
+            key_tag.key_type
+        );
+    }
+    if key_tag.network != Network::MainNet {
+        bail!(
+            "ecc slots hold mainnet keys, a {} key can not be imported",
+            key_tag.network
+        );
+    }
+    keypair
+        .secret_to_vec()
+        .try_into()
+        .map_err(|_| anyhow!("invalid ecc_compact private key"))
+}
+
+fn import_key_in_slot(
+    ecc: &mut Ecc,
+    slot: u8,
+    private_key: &[u8; 32],
+    write_key: Option<&Path>,
+    force: bool,
+    num_in: &[u8; 20],
+) -> Result<Keypair> {
+    if !force && compact_key_in_slot(ecc, slot).is_ok() {
+        bail!("slot {slot} already holds a key, use --force to replace it");
+    }
+    let write_key = if ecc.get_locked(&ecc608::Zone::Data)? {
+        let slot_config = ecc.get_slot_config(slot)?;
+        if slot_config.write_config(WriteCommand::PrivWrite)
+            == WriteConfig::PrivWrite(PrivWriteConfig::Invalid)
+        {
+            bail!("slot {slot} is not configured for PrivWrite, a key can not be written to it");
+        }
+        let path = write_key.ok_or_else(|| {
+            anyhow!(
+                "the data zone is locked, importing into slot {slot} needs the write key of slot {}, add write-key=<path> to the device url",
+                slot_config.write_key()
+            )
+        })?;
+        Some((slot_config.write_key(), load_write_key(path)?))
+    } else {
+        None
+    };
+    ecc.priv_write(
+        slot,
+        private_key,
+        write_key.as_ref().map(|(slot, key)| (*slot, key)),
+        num_in,
+    )
+    .map_err(|err| anyhow!("failed to write the key into slot {slot}: {err}"))?;
+    compact_key_in_slot(ecc, slot)
+}
+
 fn generate_compact_key_in_slot(ecc: &mut Ecc, slot: u8) -> Result<Keypair> {
     let mut try_count = 5;
     loop {
@@ -153,6 +463,30 @@ fn generate_compact_key_in_slot(ecc: &mut Ecc, slot: u8) -> Result<Keypair> {
     }
 }
 
+/// Size of the config zone in bytes
+const CONFIG_ZONE_SIZE: usize = 128;
+/// Config zone byte ranges holding the serial number
+const SERIAL_OFFSETS: [std::ops::Range<usize>; 2] = [0..4, 8..13];
+/// Offset of the little endian SlotLocked bits in the config zone
+const SLOT_LOCKED_OFFSET: usize = 88;
+
+fn read_config_zone(ecc: &mut Ecc) -> Result<Bytes> {
+    let mut zone = BytesMut::with_capacity(CONFIG_ZONE_SIZE);
+    for block in 0..(CONFIG_ZONE_SIZE / 32) as u8 {
+        zone.extend_from_slice(&ecc.read(true, Address::config(block, 0)?)?);
+    }
+    Ok(zone.freeze())
+}
+
+/// A slot is locked when its bit in SlotLocked is cleared.
+fn slot_locked(config_zone: &[u8], slot: u8) -> bool {
+    let bits = u16::from_le_bytes([
+        config_zone[SLOT_LOCKED_OFFSET],
+        config_zone[SLOT_LOCKED_OFFSET + 1],
+    ]);
+    bits & (1 << slot) == 0
+}
+
 fn get_zone_config(zone: ecc608::Zone) -> Result<ZoneConfig> {
     let config = with_ecc(|ecc| ecc.get_locked(&zone)).map(|locked| ZoneConfig { zone, locked })?;
     Ok(config)
@@ -162,15 +496,67 @@ fn get_zone_config(zone: ecc608::Zone) -> Result<ZoneConfig> {
 pub struct Info {
     #[serde(serialize_with = "serialize_bytes")]
     info: Bytes,
-    #[serde(serialize_with = "serialize_bytes")]
+    /// The part name decoded from the info bytes, if known
+    part: Option<&'static str>,
+    /// The silicon revision from the info bytes
+    revision: u8,
+    #[serde(serialize_with = "serialize_serial")]
     serial: Bytes,
 }
 
+impl Info {
+    fn new(info: Bytes, serial: Bytes) -> Self {
+        let (part, revision) = decode_revision(&info);
+        Self {
+            info,
+            part,
+            revision,
+            serial,
+        }
+    }
+
+    /// Returns the serial number in its canonical uppercase hex form.
+    pub fn serial(&self) -> String {
+        format!("{:X}", self.serial)
+    }
+}
+
+/// Decodes the 4 revision bytes returned by the info command into a part name
+/// and silicon revision. Byte 2 identifies the device family and byte 3 the
+/// silicon revision within that family.
+fn decode_revision(info: &[u8]) -> (Option<&'static str>, u8) {
+    match *info {
+        [_, _, 0x50, revision] => (Some("ATECC508A"), revision),
+        [_, _, 0x60, revision @ 0x02] => (Some("ATECC608A"), revision),
+        [_, _, 0x60, revision @ 0x03] => (Some("ATECC608B"), revision),
+        [_, _, _, revision] => (None, revision),
+        _ => (None, 0),
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct Config {
     key_config: ecc608::KeyConfig,
     slot_config: ecc608::SlotConfig,
     zones: Vec<ZoneConfig>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    slots: Option<Vec<SlotInfo>>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    config_zone: Option<String>,
+}
+
+#[derive(Debug, Serialize)]
+pub struct SlotInfo {
+    slot: u8,
+    locked: bool,
+    slot_config: ecc608::SlotConfig,
+    key_config: ecc608::KeyConfig,
+}
+
+impl Config {
+    pub fn locked(&self) -> bool {
+        self.zones.iter().all(|zone| zone.locked)
+    }
 }
 
 #[derive(Debug, Serialize)]
@@ -187,6 +573,13 @@ where
     s.serialize_str(&format!("{bytes:#02x}"))
 }
 
+pub fn serialize_serial<S>(bytes: &Bytes, s: S) -> std::result::Result<S::Ok, S::Error>
+where
+    S: Serializer,
+{
+    s.serialize_str(&format!("{bytes:X}"))
+}
+
 pub fn serialize_zone<S>(zone: &ecc608::Zone, s: S) -> std::result::Result<S::Ok, S::Error>
 where
     S: Serializer,
@@ -196,6 +589,8 @@ where
 
 #[derive(Debug)]
 pub enum Test {
+    Part,
+    Command(Command),
     ZoneLocked(ecc608::Zone),
     SlotConfig {
         slot: u8,
@@ -210,9 +605,31 @@ pub enum Test {
     Ecdh(u8),
 }
 
+/// A non destructive chip command used to check communication settings
+#[derive(Debug, Clone, Copy)]
+pub enum Command {
+    Info,
+    Read,
+    Random,
+    Nonce,
+}
+
+impl fmt::Display for Command {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Info => f.write_str("info"),
+            Self::Read => f.write_str("read"),
+            Self::Random => f.write_str("random"),
+            Self::Nonce => f.write_str("nonce"),
+        }
+    }
+}
+
 impl fmt::Display for Test {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::Part => f.write_str("part"),
+            Self::Command(command) => f.write_fmt(format_args!("command({command})")),
             Self::ZoneLocked(zone) => {
                 let zone_str = match zone {
                     ecc608::Zone::Config => "config",
@@ -244,6 +661,8 @@ impl Test {
 
     pub fn run(&self) -> TestResult {
         match self {
+            Self::Part => check_part(),
+            Self::Command(command) => check_command(command),
             Self::ZoneLocked(zone) => check_zone_locked(zone),
             Self::SlotConfig { slot, .. } => check_slot_config(*slot),
             Self::KeyConfig { slot, .. } => check_key_config(*slot),
@@ -254,6 +673,25 @@ impl Test {
     }
 }
 
+fn check_part() -> TestResult {
+    let info = with_ecc(|ecc| ecc.get_info())?;
+    match decode_revision(&info) {
+        (Some(part), _) => test::pass(part).into(),
+        (None, _) => test::expected("supported part".to_string(), format!("{info:#02x}")).into(),
+    }
+}
+
+fn check_command(command: &Command) -> TestResult {
+    let start = Instant::now();
+    with_ecc(|ecc| match command {
+        Command::Info => ecc.get_info().map(|_| ()),
+        Command::Read => ecc.read(true, Address::config(0, 0)?).map(|_| ()),
+        Command::Random => ecc.random().map(|_| ()),
+        Command::Nonce => ecc.nonce(DataBuffer::TempKey, &[0u8; 32]),
+    })?;
+    test::pass(format!("{}ms", start.elapsed().as_millis())).into()
+}
+
 fn check_zone_locked(zone: &ecc608::Zone) -> TestResult {
     match with_ecc(|ecc| ecc.get_locked(zone))? {
         true => test::pass("ok").into(),
@@ -343,7 +781,6 @@ fn check_sign(slot: u8) -> TestResult {
 }
 
 fn check_ecdh(slot: u8) -> TestResult {
-    use rand::rngs::OsRng;
     let keypair = with_ecc(|ecc| compact_key_in_slot(ecc, slot))?;
     let other_keypair = Keypair::generate(
         KeyTag {
@@ -364,3 +801,126 @@ fn check_ecdh(slot: u8) -> TestResult {
     }
     test::pass("ok").into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use ecc608_linux::sim::SimChip;
+
+    const WRITE_KEY: [u8; 32] = [0x22; 32];
+    const NUM_IN: [u8; 20] = [0x33; 20];
+
+    fn generate_keypair(network: Network) -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network,
+                key_type: KeyType::EccCompact,
+            },
+            &mut OsRng,
+        )
+    }
+
+    fn import(
+        ecc: &mut Ecc,
+        keypair: &Keypair,
+        write_key: Option<&Path>,
+        force: bool,
+    ) -> Result<Keypair> {
+        let private_key = import_private_key(keypair)?;
+        import_key_in_slot(ecc, 0, &private_key, write_key, force, &NUM_IN)
+    }
+
+    fn device(url: &str) -> Result<Device> {
+        Device::from_url(&url.parse().expect("url"))
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(
+            device("ecc://i2c-1").expect("default").address,
+            DEFAULT_ADDRESS
+        );
+        assert_eq!(device("ecc://i2c-1:0").expect("lowest").address, 0);
+        assert_eq!(
+            device("ecc://i2c-1:127").expect("highest").address,
+            MAX_ADDRESS
+        );
+        assert!(device("ecc://i2c-1:128").is_err());
+    }
+
+    #[test]
+    fn gateway_keypair() {
+        let device = Device {
+            config_path: Some("ecc.toml".to_string()),
+            write_key: Some(PathBuf::from("/lab/write.key")),
+            ..device("ecc://i2c-1:96?slot=3").expect("url")
+        };
+        assert_eq!(device.get_gateway_keypair(), "ecc://i2c-1:96?slot=3");
+        assert!(device.to_string().contains("write-key="));
+    }
+
+    #[test]
+    fn slot() {
+        assert_eq!(device("ecc://i2c-1?slot=0").expect("first").slot, 0);
+        assert_eq!(
+            device("ecc://i2c-1?slot=15").expect("last").slot,
+            ecc608::MAX_SLOT
+        );
+        assert!(device("ecc://i2c-1?slot=16").is_err());
+        assert!(device("ecc://i2c-1?slot=0&slot=1").is_err());
+        assert!(device("ecc://i2c-1?bus=2").is_err());
+    }
+
+    #[test]
+    fn import_unlocked() {
+        let mut ecc = Ecc::simulated(SimChip::new(false));
+        let keypair = generate_keypair(Network::MainNet);
+        let imported = import(&mut ecc, &keypair, None, false).expect("import");
+        assert_eq!(imported.public_key(), keypair.public_key());
+
+        let other = generate_keypair(Network::MainNet);
+        assert!(import(&mut ecc, &other, None, false).is_err());
+        let key = compact_key_in_slot(&mut ecc, 0).expect("key");
+        assert_eq!(key.public_key(), keypair.public_key());
+
+        let imported = import(&mut ecc, &other, None, true).expect("forced import");
+        assert_eq!(imported.public_key(), other.public_key());
+    }
+
+    #[test]
+    fn import_locked() {
+        let mut chip = SimChip::new(true);
+        // Encrypted PrivWrite with the write key in slot 4
+        chip.set_slot_config(0, 0x8000 | 0x40 | 4);
+        chip.set_slot(4, &WRITE_KEY);
+        let mut ecc = Ecc::simulated(chip);
+        let keypair = generate_keypair(Network::MainNet);
+        assert!(import(&mut ecc, &keypair, None, false).is_err());
+
+        let dir = std::env::temp_dir().join(format!("gateway_mfr-ecc-{}", std::process::id()));
+        fs::create_dir_all(&dir).expect("temp dir");
+        let write_key = dir.join("write.key");
+        fs::write(&write_key, WRITE_KEY).expect("write key");
+        let imported = import(&mut ecc, &keypair, Some(&write_key), false).expect("import");
+        assert_eq!(imported.public_key(), keypair.public_key());
+
+        fs::write(&write_key, [0x23; 32]).expect("wrong write key");
+        let other = generate_keypair(Network::MainNet);
+        assert!(import(&mut ecc, &other, Some(&write_key), true).is_err());
+        fs::remove_dir_all(dir).expect("remove temp dir");
+    }
+
+    #[test]
+    fn import_rejected_before_write() {
+        let testnet = generate_keypair(Network::TestNet);
+        assert!(import_private_key(&testnet).is_err());
+        let ed25519 = Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::Ed25519,
+            },
+            &mut OsRng,
+        );
+        assert!(import_private_key(&ed25519).is_err());
+    }
+}
@@ -0,0 +1,484 @@
+use crate::{
+    anyhow, bail,
+    device::{
+        test::{self, TestResult},
+        DeviceArgs,
+    },
+    Result,
+};
+use helium_crypto::{KeyTag, KeyType, Keypair, PublicKey, Verify};
+use rand::rngs::OsRng;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    fmt,
+    io::{self, BufRead, BufReader, Write},
+    path::{Path, PathBuf},
+    process::{Child, ChildStdin, Command, Stdio},
+    sync::{
+        mpsc::{self, Receiver, RecvTimeoutError},
+        Arc, Mutex,
+    },
+    thread,
+    time::Duration,
+};
+
+/// The default number of seconds to wait for a helper response.
+const DEFAULT_TIMEOUT: u64 = 30;
+
+/// A security device implemented by an external helper program.
+///
+/// The helper is started on first use and receives one JSON request per line
+/// on stdin, answering each with one JSON response line on stdout:
+///
+/// ```text
+/// > {"id":1,"method":"sign","params":{"data":"<hex>"}}
+/// < {"id":1,"result":{"signature":"<hex>"}}
+/// < {"id":1,"error":"<message>"}
+/// ```
+///
+/// The methods and their results are:
+///
+/// - `init`: prepares the security part, the result is ignored
+/// - `info`, `config`: any json value, which is printed as is
+/// - `key` (`{"create": bool}`), `provision`: `{"public_key": "<b58>"}`
+/// - `sign` (`{"data": "<hex>"}`): `{"signature": "<hex>"}`, DER encoded
+///   for ecc_compact keys
+/// - `ecdh` (`{"public_key": "<b58>"}`): `{"shared_secret": "<hex>"}`
+///
+/// The helper's stdin is closed when the tool exits, after which the helper
+/// should exit too. Anything the helper writes to stderr is passed through.
+/// A helper that does not answer a request within the timeout is killed.
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The helper program path
+    pub path: PathBuf,
+    /// How long to wait for a response
+    pub timeout: Duration,
+    helper: Arc<Mutex<Option<Helper>>>,
+}
+
+/// A running helper. Its stdout is read by a separate thread so responses
+/// can be waited for with a timeout.
+#[derive(Debug)]
+struct Helper {
+    child: Child,
+    stdin: ChildStdin,
+    responses: Receiver<io::Result<String>>,
+    next_id: u64,
+}
+
+#[derive(Debug, Serialize)]
+struct Request<'a> {
+    id: u64,
+    method: &'a str,
+    #[serde(skip_serializing_if = "Value::is_null")]
+    params: Value,
+}
+
+#[derive(Debug, Deserialize)]
+struct Response {
+    id: u64,
+    #[serde(default)]
+    result: Value,
+    error: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct KeyResult {
+    public_key: PublicKey,
+}
+
+#[derive(Debug, Deserialize)]
+struct SignResult {
+    signature: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct EcdhResult {
+    shared_secret: String,
+}
+
+/// A key held by an exec helper. The private key stays with the helper, so
+/// signing is a request to the helper.
+#[derive(Debug)]
+pub struct Key {
+    device: Device,
+    public_key: PublicKey,
+}
+
+impl Device {
+    /// Parses an exec device url of the form `exec:///<path>?timeout=<secs>`,
+    /// where <path> is the absolute path of the helper program and <secs> the
+    /// number of seconds to wait for a response (default 30).
+    pub fn from_url(url: &str) -> Result<Self> {
+        let (path, query) = match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        };
+        let args = DeviceArgs::from_query(url, query, &["timeout"])?;
+        let timeout = args.get_in_range("timeout", DEFAULT_TIMEOUT, 1..=3600)?;
+        let path = path
+            .strip_prefix("exec://")
+            .ok_or_else(|| anyhow!("invalid exec url \"{url}\": expected exec:///<path>"))?;
+        if !path.starts_with('/') {
+            bail!(
+                "invalid exec url \"{url}\": the helper path must be absolute, use exec:///<path>"
+            );
+        }
+        Ok(Self {
+            path: PathBuf::from(path),
+            timeout: Duration::from_secs(timeout),
+            helper: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    pub fn init(&self) -> Result {
+        self.call("init", Value::Null)?;
+        Ok(())
+    }
+
+    pub fn get_info(&self) -> Result<Value> {
+        self.call("info", Value::Null)
+    }
+
+    pub fn get_config(&self) -> Result<Value> {
+        self.call("config", Value::Null)
+    }
+
+    pub fn get_keypair(&self, create: bool) -> Result<Key> {
+        let result = self.call("key", json!({ "create": create }))?;
+        self.to_key(result)
+    }
+
+    pub fn provision(&self) -> Result<Key> {
+        let result = self.call("provision", Value::Null)?;
+        self.to_key(result)
+    }
+
+    pub fn get_tests(&self) -> Vec<Test> {
+        vec![
+            Test::MinerKey(self.clone()),
+            Test::Sign(self.clone()),
+            Test::Ecdh(self.clone()),
+        ]
+    }
+
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let result = self.call("sign", json!({ "data": to_hex(data) }))?;
+        let result: SignResult = serde_json::from_value(result)?;
+        from_hex(&result.signature)
+    }
+
+    pub fn ecdh(&self, public_key: &PublicKey) -> Result<Vec<u8>> {
+        let result = self.call("ecdh", json!({ "public_key": public_key.to_string() }))?;
+        let result: EcdhResult = serde_json::from_value(result)?;
+        from_hex(&result.shared_secret)
+    }
+
+    fn to_key(&self, result: Value) -> Result<Key> {
+        let result: KeyResult = serde_json::from_value(result)
+            .map_err(|err| anyhow!("invalid key from {}: {err}", self.path.display()))?;
+        Ok(Key {
+            device: self.clone(),
+            public_key: result.public_key,
+        })
+    }
+
+    /// Sends a request to the helper, starting it if needed, and waits for
+    /// the response. The helper is killed when it does not answer in time, and
+    /// started again by the next request.
+    fn call(&self, method: &str, params: Value) -> Result<Value> {
+        let mut guard = self
+            .helper
+            .lock()
+            .map_err(|_| anyhow!("exec helper lock poisoned"))?;
+        let helper = match guard.as_mut() {
+            Some(helper) => helper,
+            None => guard.insert(Helper::spawn(&self.path)?),
+        };
+        helper.next_id += 1;
+        let request = Request {
+            id: helper.next_id,
+            method,
+            params,
+        };
+        writeln!(helper.stdin, "{}", serde_json::to_string(&request)?)?;
+        helper.stdin.flush()?;
+
+        let line = match helper.responses.recv_timeout(self.timeout) {
+            Ok(line) => line?,
+            Err(RecvTimeoutError::Timeout) => {
+                if let Some(mut helper) = guard.take() {
+                    let _ = helper.child.kill();
+                    let _ = helper.child.wait();
+                }
+                bail!(
+                    "exec helper {} did not answer {method} within {} seconds",
+                    self.path.display(),
+                    self.timeout.as_secs()
+                );
+            }
+            Err(RecvTimeoutError::Disconnected) => {
+                bail!("exec helper {} exited", self.path.display())
+            }
+        };
+        let response: Response = serde_json::from_str(&line)
+            .map_err(|err| anyhow!("invalid response from {}: {err}", self.path.display()))?;
+        if response.id != request.id {
+            bail!(
+                "unexpected response id {} from {}, expected {}",
+                response.id,
+                self.path.display(),
+                request.id
+            );
+        }
+        match response.error {
+            Some(error) => bail!("{method} failed: {error}"),
+            None => Ok(response.result),
+        }
+    }
+}
+
+impl Helper {
+    fn spawn(path: &Path) -> Result<Self> {
+        let mut child = Command::new(path)
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::inherit())
+            .spawn()
+            .map_err(|err| anyhow!("failed to start exec helper {}: {err}", path.display()))?;
+        let stdin = child
+            .stdin
+            .take()
+            .ok_or_else(|| anyhow!("missing exec helper stdin"))?;
+        let stdout = child
+            .stdout
+            .take()
+            .ok_or_else(|| anyhow!("missing exec helper stdout"))?;
+        let (sender, responses) = mpsc::channel();
+        thread::spawn(move || {
+            for line in BufReader::new(stdout).lines() {
+                if sender.send(line).is_err() {
+                    break;
+                }
+            }
+        });
+        Ok(Self {
+            child,
+            stdin,
+            responses,
+            next_id: 0,
+        })
+    }
+}
+
+impl Key {
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        self.device.sign(msg)
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "exec://{}", self.path.display())?;
+        if self.timeout.as_secs() != DEFAULT_TIMEOUT {
+            write!(f, "?timeout={}", self.timeout.as_secs())?;
+        }
+        Ok(())
+    }
+}
+
+fn to_hex(data: &[u8]) -> String {
+    data.iter().map(|byte| format!("{byte:02x}")).collect()
+}
+
+fn from_hex(s: &str) -> Result<Vec<u8>> {
+    if !s.len().is_multiple_of(2) || !s.is_ascii() {
+        bail!("invalid hex string \"{s}\"");
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|index| {
+            u8::from_str_radix(&s[index..index + 2], 16)
+                .map_err(|_| anyhow!("invalid hex string \"{s}\""))
+        })
+        .collect()
+}
+
+#[derive(Debug)]
+pub enum Test {
+    MinerKey(Device),
+    Sign(Device),
+    Ecdh(Device),
+}
+
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinerKey(device) => f.write_fmt(format_args!("miner_key({device})")),
+            Self::Sign(device) => f.write_fmt(format_args!("sign({device})")),
+            Self::Ecdh(device) => f.write_fmt(format_args!("ecdh({device})")),
+        }
+    }
+}
+
+impl Test {
+    pub fn run(&self) -> TestResult {
+        match self {
+            Self::MinerKey(device) => check_miner_key(device),
+            Self::Sign(device) => check_sign(device),
+            Self::Ecdh(device) => check_ecdh(device),
+        }
+    }
+}
+
+fn check_miner_key(device: &Device) -> TestResult {
+    let key = device.get_keypair(false)?;
+    test::pass(key.public_key()).into()
+}
+
+fn check_sign(device: &Device) -> TestResult {
+    const DATA: &[u8] = b"hello world";
+    let key = device.get_keypair(false)?;
+    let signature = key.sign(DATA)?;
+    key.public_key().verify(DATA, &signature)?;
+    test::pass("ok").into()
+}
+
+fn check_ecdh(device: &Device) -> TestResult {
+    let key = device.get_keypair(false)?;
+    let other_keypair = Keypair::generate(
+        KeyTag {
+            network: key.public_key().network,
+            key_type: KeyType::EccCompact,
+        },
+        &mut OsRng,
+    );
+    let exec_shared_secret = device.ecdh(other_keypair.public_key())?;
+    let other_shared_secret = other_keypair.ecdh(key.public_key())?;
+
+    if exec_shared_secret.as_slice() != other_shared_secret.as_bytes().as_slice() {
+        return test::expected(
+            to_hex(&exec_shared_secret),
+            to_hex(other_shared_secret.as_bytes()),
+        )
+        .into();
+    }
+    test::pass("ok").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use helium_crypto::{Network, Sign};
+    use std::{env, fs, os::unix::fs::PermissionsExt, process, time::Instant};
+
+    /// Writes a helper script which answers `key`, `sign` and `ecdh` with the
+    /// given canned results, and never answers `info`.
+    fn helper_script(
+        name: &str,
+        public_key: &PublicKey,
+        data: &[u8],
+        signature: &[u8],
+        other_key: &PublicKey,
+        shared_secret: &[u8],
+    ) -> PathBuf {
+        let path = env::temp_dir().join(format!("gateway_mfr-{name}-{}.sh", process::id()));
+        let script = format!(
+            r#"#!/bin/sh
+while read -r line; do
+    id=$(printf '%s' "$line" | sed 's/^{{"id":\([0-9]*\),.*/\1/')
+    method=$(printf '%s' "$line" | sed 's/.*"method":"\([a-z]*\)".*/\1/')
+    case "$method:$line" in
+        init:*) echo "{{\"id\":$id,\"result\":null}}" ;;
+        key:*) echo "{{\"id\":$id,\"result\":{{\"public_key\":\"{public_key}\"}}}}" ;;
+        sign:*'"data":"{data}"'*) echo "{{\"id\":$id,\"result\":{{\"signature\":\"{signature}\"}}}}" ;;
+        ecdh:*'"public_key":"{other_key}"'*) echo "{{\"id\":$id,\"result\":{{\"shared_secret\":\"{shared_secret}\"}}}}" ;;
+        info:*) exec sleep 60 ;;
+        *) echo "{{\"id\":$id,\"error\":\"unexpected $method\"}}" ;;
+    esac
+done
+"#,
+            data = to_hex(data),
+            signature = to_hex(signature),
+            shared_secret = to_hex(shared_secret),
+        );
+        fs::write(&path, script).expect("helper script");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o755)).expect("helper mode");
+        path
+    }
+
+    fn generate_keypair() -> Keypair {
+        Keypair::generate(
+            KeyTag {
+                network: Network::MainNet,
+                key_type: KeyType::EccCompact,
+            },
+            &mut OsRng,
+        )
+    }
+
+    #[test]
+    fn protocol() {
+        const DATA: &[u8] = b"hello world";
+        let keypair = generate_keypair();
+        let other_keypair = generate_keypair();
+        let signature = keypair.sign(DATA).expect("signature");
+        let shared_secret = other_keypair.ecdh(keypair.public_key()).expect("ecdh");
+        let path = helper_script(
+            "exec-helper",
+            keypair.public_key(),
+            DATA,
+            &signature,
+            other_keypair.public_key(),
+            shared_secret.as_bytes(),
+        );
+        let device =
+            Device::from_url(&format!("exec://{}?timeout=1", path.display())).expect("exec url");
+
+        device.init().expect("init");
+        let key = device.get_keypair(false).expect("key");
+        assert_eq!(key.public_key(), keypair.public_key());
+        let signature = key.sign(DATA).expect("sign");
+        key.public_key()
+            .verify(DATA, &signature)
+            .expect("verify signature");
+        let secret = device.ecdh(other_keypair.public_key()).expect("ecdh");
+        assert_eq!(secret.as_slice(), shared_secret.as_bytes().as_slice());
+
+        let err = device.sign(b"other data").expect_err("sign error");
+        assert_eq!(err.to_string(), "sign failed: unexpected sign");
+
+        // A helper that does not answer is killed, and the next request
+        // starts it again
+        let start = Instant::now();
+        let err = device.get_info().expect_err("timeout");
+        assert!(err.to_string().contains("did not answer info"), "{err}");
+        assert!(start.elapsed() < Duration::from_secs(10));
+        let key = device.get_keypair(false).expect("key after restart");
+        assert_eq!(key.public_key(), keypair.public_key());
+
+        fs::remove_file(path).expect("remove helper script");
+    }
+
+    #[test]
+    fn url() {
+        let device = Device::from_url("exec:///usr/lib/acme/se-helper").expect("exec url");
+        assert_eq!(device.timeout, Duration::from_secs(DEFAULT_TIMEOUT));
+        assert_eq!(device.to_string(), "exec:///usr/lib/acme/se-helper");
+        let device =
+            Device::from_url("exec:///usr/lib/acme/se-helper?timeout=5").expect("exec url");
+        assert_eq!(device.timeout, Duration::from_secs(5));
+        assert_eq!(
+            device.to_string(),
+            "exec:///usr/lib/acme/se-helper?timeout=5"
+        );
+        assert!(Device::from_url("exec:///usr/lib/acme/se-helper?timeout=0").is_err());
+        assert!(Device::from_url("exec://se-helper").is_err());
+    }
+}
@@ -0,0 +1,412 @@
+use crate::{
+    anyhow, bail,
+    device::{
+        test::{self, TestResult},
+        DeviceArgs, Overwrite,
+    },
+    Result,
+};
+use helium_crypto::{KeyTag, KeyType, Keypair, Sign, Verify};
+use http::Uri;
+use linux_keyutils::{Key, KeyRing, KeyRingIdentifier};
+use percent_encoding::{percent_decode_str, utf8_percent_encode, AsciiSet, CONTROLS};
+use rand::rngs::OsRng;
+use serde::Serialize;
+use std::{fmt, str::FromStr};
+
+/// Characters which are escaped when a key description is rendered in a url
+const DESCRIPTION_ESCAPE: &AsciiSet = &CONTROLS.add(b' ').add(b'#').add(b'%').add(b'?');
+
+/// The most links read from a keyring, 64KiB worth of key ids like the
+/// largest key payload
+const MAX_LINKS: usize = 16384;
+
+/// A keypair stored in a Linux kernel keyring. The key never touches the
+/// disk, and is only readable by processes that possess the keyring.
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The keyring holding the key
+    pub keyring: Keyring,
+    /// The description the key is stored under
+    pub description: String,
+    /// Whether an existing key may be replaced
+    pub overwrite: Overwrite,
+}
+
+/// The kernel keyrings a key can be stored in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Keyring {
+    /// The keyring of the current user, shared by all of their sessions
+    User,
+    /// The keyring of the current login session
+    Session,
+    /// The persistent keyring of the current user, which survives logouts
+    Persistent,
+}
+
+impl Device {
+    /// Parses a keyring device url of the form
+    /// `keyring://<keyring>/<description>`, where <keyring> is one of `user`,
+    /// `session` or `persistent` and <description> is the (percent-encoded)
+    /// description of the key in the keyring.
+    pub fn from_url(url: &Uri) -> Result<Self> {
+        DeviceArgs::from_uri(url, &[])?;
+        let keyring = url
+            .host()
+            .ok_or_else(|| anyhow!("missing keyring in \"{url}\""))?
+            .parse()?;
+        let description = percent_decode_str(url.path().trim_start_matches('/'))
+            .decode_utf8()
+            .map_err(|err| anyhow!("invalid keyring url \"{url}\": {err}"))?
+            .to_string();
+        if description.is_empty() {
+            bail!("invalid keyring url \"{url}\": missing key description");
+        }
+        Ok(Self {
+            keyring,
+            description,
+            overwrite: Overwrite::default(),
+        })
+    }
+
+    pub fn get_info(&self) -> Result<Info> {
+        let key = self.find_key()?;
+        let keypair = Keypair::try_from(&key.read_to_vec()?[..])?;
+        Ok(Info {
+            r#type: keypair.key_tag().key_type.to_string(),
+            keyring: self.keyring,
+            description: self.description.clone(),
+            id: key.get_id().as_raw_id(),
+        })
+    }
+
+    pub fn get_keypair(&self, create: bool) -> Result<Keypair> {
+        let keyring = self.keyring.open()?;
+        let key = match (find_linked(&keyring, &self.description)?, create) {
+            (Some(key), false) => key,
+            (None, false) => return Err(self.missing_error()),
+            (Some(_), true) if !self.overwrite.force => return Err(self.exists_error()),
+            (_, true) => {
+                let keypair = Keypair::generate(KeyTag::default(), &mut OsRng);
+                keyring.add_key(&self.description, &keypair.to_vec())?
+            }
+        };
+        let data = key.read_to_vec()?;
+        Ok(Keypair::try_from(&data[..])?)
+    }
+
+    pub fn provision(&self) -> Result<Keypair> {
+        self.get_keypair(true)
+    }
+
+    /// Reads the existing key, failing when there is none.
+    pub fn load_keypair(&self) -> Result<Keypair> {
+        let data = self.find_key()?.read_to_vec()?;
+        Ok(Keypair::try_from(&data[..])?)
+    }
+
+    /// Adds the keypair to the keyring, replacing an existing key only with
+    /// `--force`.
+    pub fn import_keypair(&self, keypair: &Keypair) -> Result<Keypair> {
+        let keyring = self.keyring.open()?;
+        if find_linked(&keyring, &self.description)?.is_some() && !self.overwrite.force {
+            return Err(self.exists_error());
+        }
+        let key = keyring.add_key(&self.description, &keypair.to_vec())?;
+        let data = key.read_to_vec()?;
+        Ok(Keypair::try_from(&data[..])?)
+    }
+
+    pub fn get_config(&self) -> Result<Config> {
+        let metadata = self.find_key()?.metadata()?;
+        Ok(Config {
+            keyring: self.keyring,
+            description: self.description.clone(),
+            uid: metadata.get_uid(),
+            gid: metadata.get_gid(),
+            permissions: format!("{:08x}", metadata.get_perms().bits()),
+        })
+    }
+
+    fn exists_error(&self) -> crate::Error {
+        anyhow!(
+            "key \"{}\" already exists in the {} keyring, use --force to replace it",
+            self.description,
+            self.keyring
+        )
+    }
+
+    fn missing_error(&self) -> crate::Error {
+        anyhow!(
+            "key \"{}\" not found in the {} keyring",
+            self.description,
+            self.keyring
+        )
+    }
+
+    fn find_key(&self) -> Result<Key> {
+        let keyring = self.keyring.open()?;
+        find_linked(&keyring, &self.description)?.ok_or_else(|| self.missing_error())
+    }
+
+    pub fn get_tests(&self) -> Vec<Test> {
+        vec![
+            Test::MinerKey(self.clone()),
+            Test::Sign(self.clone()),
+            Test::Ecdh(self.clone()),
+        ]
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "keyring://{}/{}",
+            self.keyring,
+            utf8_percent_encode(&self.description, DESCRIPTION_ESCAPE)
+        )
+    }
+}
+
+impl Keyring {
+    /// Opens the keyring without creating it. A keyring the kernel creates
+    /// on demand would be private to this process, so keys stored in it are
+    /// lost when the process exits. The persistent keyring is linked into
+    /// the session keyring, so that has to exist too.
+    fn open(&self) -> Result<KeyRing> {
+        let special_id = match self {
+            Self::User => KeyRingIdentifier::User,
+            Self::Session | Self::Persistent => KeyRingIdentifier::Session,
+        };
+        let keyring = KeyRing::from_special_id(special_id, false).map_err(|err| {
+            let name = match self {
+                Self::User => "user",
+                _ => "session",
+            };
+            anyhow!("the {name} keyring is not available ({err}), run in a login session or under \"keyctl session\"")
+        })?;
+        match self {
+            Self::Persistent => Ok(KeyRing::get_persistent(KeyRingIdentifier::Session)?),
+            _ => Ok(keyring),
+        }
+    }
+}
+
+/// Looks up a key by its description among the keys linked directly into the
+/// keyring. Unlike a keyring search this does not descend into keyrings linked
+/// into it.
+fn find_linked(keyring: &KeyRing, description: &str) -> Result<Option<Key>> {
+    let key = keyring
+        .get_links(MAX_LINKS)?
+        .iter()
+        .filter_map(|link| link.as_key())
+        .find(|key| {
+            key.metadata()
+                .map(|metadata| metadata.get_description() == description)
+                .unwrap_or(false)
+        });
+    Ok(key)
+}
+
+impl FromStr for Keyring {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s {
+            "user" => Ok(Self::User),
+            "session" => Ok(Self::Session),
+            "persistent" => Ok(Self::Persistent),
+            _ => Err(anyhow!(
+                "unknown keyring \"{s}\", expected one of user, session, persistent"
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Keyring {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::User => f.write_str("user"),
+            Self::Session => f.write_str("session"),
+            Self::Persistent => f.write_str("persistent"),
+        }
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    r#type: String,
+    keyring: Keyring,
+    description: String,
+    id: i32,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Config {
+    keyring: Keyring,
+    description: String,
+    uid: u32,
+    gid: u32,
+    permissions: String,
+}
+
+#[derive(Debug)]
+pub enum Test {
+    MinerKey(Device),
+    Sign(Device),
+    Ecdh(Device),
+}
+
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinerKey(device) => f.write_fmt(format_args!("miner_key({device})")),
+            Self::Sign(device) => f.write_fmt(format_args!("sign({device})")),
+            Self::Ecdh(device) => f.write_fmt(format_args!("ecdh({device})")),
+        }
+    }
+}
+
+impl Test {
+    pub fn run(&self) -> TestResult {
+        match self {
+            Self::MinerKey(device) => check_miner_key(device),
+            Self::Sign(device) => check_sign(device),
+            Self::Ecdh(device) => check_ecdh(device),
+        }
+    }
+}
+
+fn check_miner_key(device: &Device) -> TestResult {
+    let keypair = device.load_keypair()?;
+    test::pass(keypair.public_key()).into()
+}
+
+fn check_sign(device: &Device) -> TestResult {
+    const DATA: &[u8] = b"hello world";
+    let keypair = device.load_keypair()?;
+    let signature = keypair.sign(DATA)?;
+    keypair.public_key().verify(DATA, &signature)?;
+    test::pass("ok").into()
+}
+
+fn check_ecdh(device: &Device) -> TestResult {
+    let keypair = device.load_keypair()?;
+    let other_keypair = Keypair::generate(
+        KeyTag {
+            network: keypair.key_tag().network,
+            key_type: KeyType::EccCompact,
+        },
+        &mut OsRng,
+    );
+    let keyring_shared_secret = keypair.ecdh(other_keypair.public_key())?;
+    let other_shared_secret = other_keypair.ecdh(keypair.public_key())?;
+
+    if keyring_shared_secret.as_bytes() != other_shared_secret.as_bytes() {
+        return test::expected(
+            format!("{:#02x}", keyring_shared_secret.as_bytes()),
+            format!("{:#02x}", other_shared_secret.as_bytes()),
+        )
+        .into();
+    }
+    test::pass("ok").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::process;
+
+    /// Returns a device for a key that only this test uses in the session
+    /// keyring, joining a session keyring when the test runs without one.
+    fn device(name: &str) -> Device {
+        KeyRing::from_special_id(KeyRingIdentifier::Session, true).expect("session keyring");
+        let device = Device::from_url(
+            &format!("keyring://session/gateway-mfr-{name}-{}", process::id())
+                .parse()
+                .expect("uri"),
+        )
+        .expect("keyring url");
+        remove(&device);
+        device
+    }
+
+    fn remove(device: &Device) {
+        if let Ok(key) = device.find_key() {
+            key.invalidate().expect("invalidate key");
+        }
+    }
+
+    fn forced(device: &Device) -> Device {
+        Device {
+            overwrite: Overwrite {
+                force: true,
+                backup: false,
+            },
+            ..device.clone()
+        }
+    }
+
+    #[test]
+    fn url() {
+        let device = Device::from_url(&"keyring://user/miner%20key".parse().expect("uri"))
+            .expect("keyring url");
+        assert_eq!(device.keyring, Keyring::User);
+        assert_eq!(device.description, "miner key");
+        assert_eq!(device.to_string(), "keyring://user/miner%20key");
+        assert!(Device::from_url(&"keyring://thread/miner".parse().expect("uri")).is_err());
+        assert!(Device::from_url(&"keyring://user/".parse().expect("uri")).is_err());
+    }
+
+    #[test]
+    fn round_trip() {
+        let device = device("round-trip");
+        let keypair = device.provision().expect("provision");
+        assert_eq!(
+            device.load_keypair().expect("key").public_key(),
+            keypair.public_key()
+        );
+        let imported = Keypair::generate(KeyTag::default(), &mut OsRng);
+        let replaced = forced(&device)
+            .import_keypair(&imported)
+            .expect("forced import");
+        assert_eq!(replaced.public_key(), imported.public_key());
+        assert_eq!(
+            device.load_keypair().expect("key").public_key(),
+            imported.public_key()
+        );
+        remove(&device);
+    }
+
+    #[test]
+    fn force() {
+        let device = device("force");
+        let keypair = device.provision().expect("provision");
+        assert!(device.provision().is_err(), "replaced without --force");
+        let imported = Keypair::generate(KeyTag::default(), &mut OsRng);
+        assert!(
+            device.import_keypair(&imported).is_err(),
+            "imported without --force"
+        );
+        assert_eq!(
+            device.load_keypair().expect("key").public_key(),
+            keypair.public_key()
+        );
+        let replaced = forced(&device).provision().expect("forced provision");
+        assert_ne!(replaced.public_key(), keypair.public_key());
+        remove(&device);
+    }
+
+    #[test]
+    fn missing_key() {
+        let device = device("missing");
+        assert!(device.load_keypair().is_err());
+        assert!(device.get_keypair(false).is_err());
+        for test in device.get_tests() {
+            assert!(test.run().is_err(), "{test} passed without a key");
+        }
+        assert!(device.find_key().is_err(), "test created a key");
+    }
+}
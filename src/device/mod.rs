@@ -1,17 +1,26 @@
-use crate::{anyhow, Result};
-use helium_crypto::Keypair;
+use crate::{anyhow, bail, Result};
+use helium_crypto::{KeyType, Keypair, PublicKey, Sign};
 use http::Uri;
 use serde::Serialize;
-use std::{collections::HashMap, str::FromStr};
+use std::{collections::HashMap, fmt, ops::RangeInclusive, str::FromStr};
 
+pub mod discover;
+pub mod doctor;
 #[cfg(feature = "ecc608")]
 mod ecc;
+mod exec;
 mod file;
+#[cfg(feature = "keyring")]
+mod keyring;
 #[cfg(feature = "nova-tz")]
 mod nova_tz;
+#[cfg(feature = "pkcs11")]
+mod pkcs11;
 #[cfg(feature = "tpm")]
 mod tpm;
 
+pub use file::Overwrite;
+
 /// A security device to work with. Security devices come in all forms. This
 /// abstracts them into one with a well defined interface for doing what this
 /// tool needs to do with them.
@@ -23,9 +32,15 @@ pub enum Device {
     Tpm(tpm::Device),
     #[cfg(feature = "nova-tz")]
     TrustZone(nova_tz::Device),
+    #[cfg(feature = "keyring")]
+    Keyring(keyring::Device),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Device),
     File(file::Device),
+    Exec(exec::Device),
 }
 
+#[derive(Debug)]
 pub struct DeviceArgs(HashMap<String, String>);
 
 /// Represents the configuration state for the given security device. This
@@ -40,7 +55,52 @@ pub enum Config {
     Tpm(tpm::Config),
     #[cfg(feature = "nova-tz")]
     TrustZone(nova_tz::Config),
+    #[cfg(feature = "keyring")]
+    Keyring(keyring::Config),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Config),
     File(file::Config),
+    Exec(serde_json::Value),
+}
+
+/// A snapshot of the full device state, used to compare a device against a
+/// golden reference device.
+#[derive(Debug, Serialize)]
+pub struct Snapshot {
+    pub config: Config,
+    pub keys: Vec<SnapshotKey>,
+}
+
+/// The public key in a given slot, or the device key for devices without
+/// slots. The key is empty if it could not be read.
+#[derive(Debug, Serialize)]
+pub struct SnapshotKey {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub slot: Option<u8>,
+    pub key: Option<String>,
+}
+
+/// The operations a security device supports through this tool.
+#[derive(Debug, Serialize)]
+pub struct Capabilities {
+    /// Whether the device can be provisioned
+    pub provision: bool,
+    /// Whether a new key can be generated on the device
+    pub generate_key: bool,
+    /// Whether the device key can be used for ecdh, unknown when the key of a
+    /// device that holds any key type could not be loaded
+    pub ecdh: Option<bool>,
+}
+
+/// The key of a security device. Most devices provide a helium-crypto
+/// keypair, while exec helpers and pkcs11 tokens keep the private key to
+/// themselves.
+#[derive(Debug)]
+pub enum Key {
+    Keypair(Keypair),
+    Exec(exec::Key),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Key),
 }
 
 #[derive(Debug, Serialize)]
@@ -48,6 +108,13 @@ pub enum Config {
 pub enum FileConfig {
     #[cfg(feature = "ecc608")]
     Ecc(ecc::FileConfig),
+    Gateway(GatewaySettings),
+}
+
+/// The gateway-rs settings needed to use the device keypair
+#[derive(Debug, Serialize)]
+pub struct GatewaySettings {
+    pub keypair: String,
 }
 
 pub mod test {
@@ -55,11 +122,15 @@ pub mod test {
 
     #[cfg(feature = "ecc608")]
     use crate::device::ecc;
-    use crate::device::file;
+    #[cfg(feature = "keyring")]
+    use crate::device::keyring;
     #[cfg(feature = "nova-tz")]
     use crate::device::nova_tz;
+    #[cfg(feature = "pkcs11")]
+    use crate::device::pkcs11;
     #[cfg(feature = "tpm")]
     use crate::device::tpm;
+    use crate::device::{doctor, exec, file};
 
     use serde::Serialize;
     use std::{collections::HashMap, fmt};
@@ -72,7 +143,13 @@ pub mod test {
         Tpm(tpm::Test),
         #[cfg(feature = "nova-tz")]
         TrustZone(nova_tz::Test),
+        #[cfg(feature = "keyring")]
+        Keyring(keyring::Test),
+        #[cfg(feature = "pkcs11")]
+        Pkcs11(pkcs11::Test),
         File(file::Test),
+        Exec(exec::Test),
+        Doctor(doctor::Check),
     }
 
     #[derive(Debug, Serialize, Clone)]
@@ -120,7 +197,13 @@ pub mod test {
                 Self::Tpm(test) => test.run(),
                 #[cfg(feature = "nova-tz")]
                 Self::TrustZone(test) => test.run(),
+                #[cfg(feature = "keyring")]
+                Self::Keyring(test) => test.run(),
+                #[cfg(feature = "pkcs11")]
+                Self::Pkcs11(test) => test.run(),
                 Self::File(test) => test.run(),
+                Self::Exec(test) => test.run(),
+                Self::Doctor(check) => check.run(),
             }
         }
     }
@@ -134,7 +217,13 @@ pub mod test {
                 Self::Tpm(test) => test.fmt(f),
                 #[cfg(feature = "nova-tz")]
                 Self::TrustZone(test) => test.fmt(f),
+                #[cfg(feature = "keyring")]
+                Self::Keyring(test) => test.fmt(f),
+                #[cfg(feature = "pkcs11")]
+                Self::Pkcs11(test) => test.fmt(f),
                 Self::File(test) => test.fmt(f),
+                Self::Exec(test) => test.fmt(f),
+                Self::Doctor(check) => check.fmt(f),
             }
         }
     }
@@ -165,6 +254,16 @@ impl FromStr for Device {
     type Err = crate::Error;
 
     fn from_str(s: &str) -> Result<Self> {
+        if file::Device::is_file_url(s) {
+            return Ok(Self::File(file::Device::from_url(s)?));
+        }
+        if s.starts_with("exec:") {
+            return Ok(Self::Exec(exec::Device::from_url(s)?));
+        }
+        #[cfg(feature = "pkcs11")]
+        if s.starts_with("pkcs11:") {
+            return Ok(Self::Pkcs11(pkcs11::Device::from_url(s)?));
+        }
         let url: Uri = s
             .parse()
             .map_err(|err| anyhow!("invalid device url \"{s}\": {err:?}"))?;
@@ -175,22 +274,82 @@ impl FromStr for Device {
             Some("tpm") => Ok(Self::Tpm(tpm::Device::from_url(&url)?)),
             #[cfg(feature = "nova-tz")]
             Some("nova-tz") => Ok(Self::TrustZone(nova_tz::Device::from_url(&url)?)),
-            Some("file") | None => Ok(Self::File(file::Device::from_url(&url)?)),
+            #[cfg(feature = "keyring")]
+            Some("keyring") => Ok(Self::Keyring(keyring::Device::from_url(&url)?)),
+            None => Err(anyhow!(
+                "invalid device url \"{s}\": missing scheme, use file:{s} for a key file"
+            )),
             _ => Err(anyhow!("invalid device url \"{s}\"")),
         }
     }
 }
 
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => device.fmt(f),
+            #[cfg(feature = "tpm")]
+            Self::Tpm(device) => device.fmt(f),
+            #[cfg(feature = "nova-tz")]
+            Self::TrustZone(device) => device.fmt(f),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => device.fmt(f),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => device.fmt(f),
+            Self::File(device) => device.fmt(f),
+            Self::Exec(device) => device.fmt(f),
+        }
+    }
+}
+
 impl DeviceArgs {
-    #[cfg(feature = "ecc608")]
-    pub(crate) fn from_uri(url: &Uri) -> Result<Self> {
-        let args = url
-            .query()
+    /// Parses the query arguments of the given url.
+    #[cfg(any(
+        feature = "ecc608",
+        feature = "tpm",
+        feature = "nova-tz",
+        feature = "keyring"
+    ))]
+    pub(crate) fn from_uri(url: &Uri, accepted: &[&str]) -> Result<Self> {
+        Self::from_query(&url.to_string(), url.query(), accepted)
+    }
+
+    /// Parses the given url query, rejecting any argument which is not in the
+    /// list of accepted arguments for the device.
+    pub(crate) fn from_query(url: &str, query: Option<&str>, accepted: &[&str]) -> Result<Self> {
+        let pairs = query
             .map_or_else(
-                || Ok(HashMap::new()),
-                serde_urlencoded::from_str::<HashMap<String, String>>,
+                || Ok(Vec::new()),
+                serde_urlencoded::from_str::<Vec<(String, String)>>,
             )
             .map_err(|err| anyhow!("invalid device url \"{url}\": {err:?}"))?;
+        let mut args = HashMap::with_capacity(pairs.len());
+        for (name, value) in pairs {
+            if args.contains_key(&name) {
+                bail!("invalid device url \"{url}\": duplicate argument {name}");
+            }
+            args.insert(name, value);
+        }
+        let mut unknown: Vec<&String> = args
+            .keys()
+            .filter(|name| !accepted.contains(&name.as_str()))
+            .collect();
+        if !unknown.is_empty() {
+            unknown.sort();
+            let unknown = unknown
+                .iter()
+                .map(|name| name.as_str())
+                .collect::<Vec<&str>>()
+                .join(", ");
+            if accepted.is_empty() {
+                bail!("invalid device url \"{url}\": unknown arguments {unknown}, this device accepts no arguments");
+            }
+            bail!(
+                "invalid device url \"{url}\": unknown arguments {unknown}, expected one of {}",
+                accepted.join(", ")
+            );
+        }
         Ok(Self(args))
     }
 
@@ -209,6 +368,24 @@ impl DeviceArgs {
             .unwrap_or(Ok(default))
             .map_err(|err| anyhow!("invalid uri argument for {name}: {err:?}"))
     }
+
+    /// Gets the argument with the given name and checks that it is in the
+    /// given range.
+    pub fn get_in_range<T>(&self, name: &str, default: T, range: RangeInclusive<T>) -> Result<T>
+    where
+        T: std::str::FromStr + PartialOrd + fmt::Display,
+        <T as std::str::FromStr>::Err: std::fmt::Debug,
+    {
+        let value = self.get(name, default)?;
+        if !range.contains(&value) {
+            bail!(
+                "invalid uri argument for {name}: {value} is not between {} and {}",
+                range.start(),
+                range.end()
+            );
+        }
+        Ok(value)
+    }
 }
 
 impl Device {
@@ -216,6 +393,11 @@ impl Device {
         match self {
             #[cfg(feature = "ecc608")]
             Self::Ecc(device) => device.init(),
+            #[cfg(feature = "tpm")]
+            Self::Tpm(device) => device.init(),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => device.init(),
+            Self::Exec(device) => device.init(),
             _ => Ok(()),
         }
     }
@@ -228,7 +410,12 @@ impl Device {
             Self::Tpm(device) => Info::Tpm(device.get_info()?),
             #[cfg(feature = "nova-tz")]
             Self::TrustZone(device) => Info::TrustZone(device.get_info()?),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => Info::Keyring(device.get_info()?),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => Info::Pkcs11(device.get_info()?),
             Self::File(device) => Info::File(device.get_info()?),
+            Self::Exec(device) => Info::Exec(device.get_info()?),
         };
         Ok(info)
     }
@@ -241,35 +428,183 @@ impl Device {
             Self::Tpm(device) => Config::Tpm(device.get_config()?),
             #[cfg(feature = "nova-tz")]
             Self::TrustZone(device) => Config::TrustZone(device.get_config()?),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => Config::Keyring(device.get_config()?),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => Config::Pkcs11(device.get_config()?),
             Self::File(device) => Config::File(device.get_config()?),
+            Self::Exec(device) => Config::Exec(device.get_config()?),
         };
         Ok(config)
     }
 
-    pub fn get_keypair(&self, create: bool) -> Result<Keypair> {
-        let keypair = match self {
+    /// Gets the configuration of every slot or key on the device. Devices
+    /// with a single key return their regular configuration.
+    pub fn get_config_all(&self) -> Result<Config> {
+        let config = match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => Config::Ecc(device.get_config_all()?),
+            _ => self.get_config()?,
+        };
+        Ok(config)
+    }
+
+    pub fn get_snapshot(&self) -> Result<Snapshot> {
+        let keys = match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => device.get_slot_keys(),
+            _ => vec![SnapshotKey {
+                slot: None,
+                key: self
+                    .load_keypair()
+                    .ok()
+                    .map(|keypair| keypair.public_key().to_string()),
+            }],
+        };
+        Ok(Snapshot {
+            config: self.get_config_all()?,
+            keys,
+        })
+    }
+
+    /// Returns the snapshot paths which are expected to differ between
+    /// otherwise identical units, like serial numbers and keys.
+    pub fn get_snapshot_unit_paths(&self) -> Vec<String> {
+        match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => device.get_snapshot_unit_paths(),
+            _ => vec!["keys".to_string()],
+        }
+    }
+
+    pub fn get_keypair(&self, create: bool) -> Result<Key> {
+        let key = match self {
             #[cfg(feature = "ecc608")]
-            Self::Ecc(device) => device.get_keypair(create)?,
+            Self::Ecc(device) => Key::Keypair(device.get_keypair(create)?),
             #[cfg(feature = "tpm")]
-            Self::Tpm(device) => device.get_keypair(create)?,
+            Self::Tpm(device) => Key::Keypair(device.get_keypair(create)?),
             #[cfg(feature = "nova-tz")]
-            Self::TrustZone(device) => device.get_keypair(create)?,
-            Self::File(device) => device.get_keypair(create)?,
+            Self::TrustZone(device) => Key::Keypair(device.get_keypair(create)?),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => Key::Keypair(device.get_keypair(create)?),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => Key::Pkcs11(device.get_keypair(create)?),
+            Self::File(device) => Key::Keypair(device.get_keypair(create)?),
+            Self::Exec(device) => Key::Exec(device.get_keypair(create)?),
         };
-        Ok(keypair)
+        Ok(key)
     }
 
-    pub fn provision(&self) -> Result<Keypair> {
-        let keypair = match self {
+    /// Returns the gateway-rs `keypair` setting for the key of this device,
+    /// failing for keys gateway-rs can not use.
+    pub fn get_gateway_keypair(&self) -> Result<String> {
+        match self {
             #[cfg(feature = "ecc608")]
-            Self::Ecc(device) => device.provision()?,
+            Self::Ecc(device) => Ok(device.get_gateway_keypair()),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(_) => bail!("gateway-rs does not support keyring keys"),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(_) => bail!("gateway-rs does not support pkcs11 keys"),
+            Self::File(device) => device.get_gateway_keypair(),
+            Self::Exec(_) => bail!("gateway-rs does not support exec helper keys"),
+            #[allow(unreachable_patterns)]
+            _ => Ok(self.to_string()),
+        }
+    }
+
+    /// Returns the existing key of the device, failing instead of creating a
+    /// key when there is none.
+    pub fn load_keypair(&self) -> Result<Key> {
+        let key = match self {
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => Key::Keypair(device.load_keypair()?),
+            Self::File(device) => Key::Keypair(device.load_keypair()?),
+            _ => self.get_keypair(false)?,
+        };
+        Ok(key)
+    }
+
+    /// Parses the source of an imported key. Imports always read a key file,
+    /// so a bare file name is taken as a path rather than a missing scheme.
+    pub fn from_key_file(s: &str) -> Result<Self> {
+        Ok(Self::File(file::Device::from_url(s)?))
+    }
+
+    /// Imports the keypair in the given key file device into this device, and
+    /// checks that the device reports the same public key afterwards.
+    pub fn import_keypair(&self, source: &Device) -> Result<Key> {
+        let Self::File(source) = source else {
+            bail!("keys can only be imported from a key file, not {source}");
+        };
+        let keypair = source.load_keypair()?;
+        let imported = match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => device.import_keypair(&keypair)?,
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => device.import_keypair(&keypair)?,
+            Self::File(device) => device.import_keypair(&keypair)?,
+            _ => bail!("importing keys is not supported for {self}"),
+        };
+        if imported.public_key() != keypair.public_key() {
+            bail!(
+                "imported public key {} does not match {}",
+                imported.public_key(),
+                keypair.public_key()
+            );
+        }
+        Ok(Key::Keypair(imported))
+    }
+
+    /// Sets how an existing key is treated when a new key is generated or
+    /// imported. File devices can refuse to overwrite or back up an existing
+    /// key, ecc slots, tpm, keyring and pkcs11 devices only replace an existing
+    /// key when forced.
+    pub fn with_overwrite(mut self, overwrite: Overwrite) -> Self {
+        match &mut self {
+            Self::File(device) => device.overwrite = overwrite,
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => device.overwrite = overwrite,
             #[cfg(feature = "tpm")]
-            Self::Tpm(device) => device.provision()?,
+            Self::Tpm(device) => device.overwrite = overwrite,
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => device.overwrite = overwrite,
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => device.overwrite = overwrite,
+            _ => (),
+        }
+        self
+    }
+
+    pub fn provision(&self) -> Result<Key> {
+        let key = match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => Key::Keypair(device.provision()?),
+            #[cfg(feature = "tpm")]
+            Self::Tpm(device) => Key::Keypair(device.provision()?),
             #[cfg(feature = "nova-tz")]
-            Self::TrustZone(device) => device.provision()?,
-            Self::File(device) => device.provision()?,
+            Self::TrustZone(device) => Key::Keypair(device.provision()?),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => Key::Keypair(device.provision()?),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => Key::Pkcs11(device.provision()?),
+            Self::File(device) => Key::Keypair(device.provision()?),
+            Self::Exec(device) => Key::Exec(device.provision()?),
         };
-        Ok(keypair)
+        Ok(key)
+    }
+
+    /// Returns tests which check that the device communicates using its
+    /// configured parameters.
+    pub fn get_config_tests(&self) -> Result<Vec<test::Test>> {
+        match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(device) => Ok(device
+                .get_config_tests()
+                .into_iter()
+                .map(test::Test::Ecc)
+                .collect()),
+            _ => Err(anyhow!("device does not support config checks")),
+        }
     }
 
     pub fn get_tests(&self) -> Vec<test::Test> {
@@ -292,19 +627,85 @@ impl Device {
                 .into_iter()
                 .map(test::Test::TrustZone)
                 .collect(),
+            #[cfg(feature = "keyring")]
+            Self::Keyring(device) => device
+                .get_tests()
+                .into_iter()
+                .map(test::Test::Keyring)
+                .collect(),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(device) => device
+                .get_tests()
+                .into_iter()
+                .map(test::Test::Pkcs11)
+                .collect(),
             Self::File(device) => device
                 .get_tests()
                 .into_iter()
                 .map(test::Test::File)
                 .collect(),
+            Self::Exec(device) => device
+                .get_tests()
+                .into_iter()
+                .map(test::Test::Exec)
+                .collect(),
         }
     }
 
+    /// Returns the supported operations. Only ecc_compact keys support ecdh,
+    /// so for devices that can hold any key type this follows from the type
+    /// of the loaded key.
+    pub fn get_capabilities(&self, key_type: Option<KeyType>) -> Capabilities {
+        let key_ecdh = key_type.map(|key_type| key_type == KeyType::EccCompact);
+        match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(_) => Capabilities {
+                provision: true,
+                generate_key: true,
+                ecdh: Some(true),
+            },
+            #[cfg(feature = "tpm")]
+            Self::Tpm(_) => Capabilities {
+                provision: true,
+                generate_key: true,
+                ecdh: Some(true),
+            },
+            #[cfg(feature = "nova-tz")]
+            Self::TrustZone(_) => Capabilities {
+                provision: false,
+                generate_key: false,
+                ecdh: Some(false),
+            },
+            #[cfg(feature = "keyring")]
+            Self::Keyring(_) => Capabilities {
+                provision: true,
+                generate_key: true,
+                ecdh: key_ecdh,
+            },
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(_) => Capabilities {
+                provision: true,
+                generate_key: true,
+                ecdh: Some(true),
+            },
+            Self::File(_) | Self::Exec(_) => Capabilities {
+                provision: true,
+                generate_key: true,
+                ecdh: key_ecdh,
+            },
+        }
+    }
+
+    /// Generates a config file for the device. ECC parts get their bus and
+    /// timing parameters, which the `config` url argument reads back, and
+    /// other devices the gateway-rs settings for their key.
     pub fn generate_config(&self) -> Result<FileConfig> {
         let config = match self {
             #[cfg(feature = "ecc608")]
             Self::Ecc(device) => FileConfig::Ecc(device.generate_config()?),
-            _ => return Err(anyhow!("device does not support config generation")),
+            _ => FileConfig::Gateway(GatewaySettings {
+                keypair: self.get_gateway_keypair()?,
+            }),
         };
         Ok(config)
     }
@@ -320,5 +721,110 @@ pub enum Info {
     Tpm(tpm::Info),
     #[cfg(feature = "nova-tz")]
     TrustZone(nova_tz::Info),
+    #[cfg(feature = "keyring")]
+    Keyring(keyring::Info),
+    #[cfg(feature = "pkcs11")]
+    Pkcs11(pkcs11::Info),
     File(file::Info),
+    Exec(serde_json::Value),
+}
+
+impl Config {
+    /// Returns whether the device configuration is locked for devices that
+    /// support locking.
+    pub fn locked(&self) -> Option<bool> {
+        match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(config) => Some(config.locked()),
+            _ => None,
+        }
+    }
+}
+
+impl Info {
+    /// Returns the serial number of the device if it has one.
+    pub fn serial(&self) -> Option<String> {
+        match self {
+            #[cfg(feature = "ecc608")]
+            Self::Ecc(info) => Some(info.serial()),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(info) => Some(info.serial()),
+            _ => None,
+        }
+    }
+}
+
+impl Key {
+    pub fn public_key(&self) -> &PublicKey {
+        match self {
+            Self::Keypair(keypair) => keypair.public_key(),
+            Self::Exec(key) => key.public_key(),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(key) => key.public_key(),
+        }
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        match self {
+            Self::Keypair(keypair) => Ok(keypair.sign(msg)?),
+            Self::Exec(key) => key.sign(msg),
+            #[cfg(feature = "pkcs11")]
+            Self::Pkcs11(key) => key.sign(msg),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACCEPTED: &[&str] = &["slot", "label"];
+
+    fn args(query: &str) -> Result<DeviceArgs> {
+        DeviceArgs::from_query("test://device", Some(query), ACCEPTED)
+    }
+
+    #[test]
+    fn from_query() {
+        let args = args("slot=3&label=miner").expect("args");
+        assert_eq!(args.get_string("slot").as_deref(), Some("3"));
+        assert_eq!(args.get_string("label").as_deref(), Some("miner"));
+        assert!(DeviceArgs::from_query("test://device", None, ACCEPTED).is_ok());
+    }
+
+    #[test]
+    fn unknown_args() {
+        let err = args("slot=1&pin=1234&bus=2").expect_err("unknown args");
+        assert!(err.to_string().contains("unknown arguments bus, pin"));
+        let err = DeviceArgs::from_query("test://device", Some("slot=1"), &[])
+            .expect_err("no args accepted");
+        assert!(err.to_string().contains("this device accepts no arguments"));
+    }
+
+    #[test]
+    fn duplicate_args() {
+        let err = args("slot=1&slot=2").expect_err("duplicate args");
+        assert!(err.to_string().contains("duplicate argument slot"));
+    }
+
+    #[test]
+    fn in_range() {
+        let slot = |query| args(query)?.get_in_range("slot", 5u16, 0..=127);
+        assert_eq!(slot("").expect("default"), 5);
+        assert_eq!(slot("slot=0").expect("lowest"), 0);
+        assert_eq!(slot("slot=127").expect("highest"), 127);
+        assert!(slot("slot=128").is_err());
+        assert!(slot("slot=-1").is_err());
+        assert!(slot("slot=one").is_err());
+    }
+
+    #[test]
+    fn generate_config() {
+        let device: Device = "file:///tmp/key.bin".parse().expect("file device");
+        let config =
+            toml::to_string(&device.generate_config().expect("config")).expect("config toml");
+        assert_eq!(config, "keypair = \"/tmp/key.bin\"\n");
+        let device: Device = "exec:///usr/bin/helper".parse().expect("exec device");
+        assert!(device.generate_config().is_err());
+    }
 }
@@ -7,7 +7,10 @@ use serde::Serialize;
 use helium_crypto::{nova_tz, Keypair, Network, Sign, Verify};
 
 use crate::{
-    device::test::{self, TestResult},
+    device::{
+        test::{self, TestResult},
+        DeviceArgs,
+    },
     Result,
 };
 
@@ -21,6 +24,7 @@ impl Device {
     /// Parses a trustzone device url of the form `nova-tz://rsa/<key_path>`,
     /// where <key_path> is the path to TrustZone keyblob
     pub fn from_url(url: &Uri) -> Result<Self> {
+        DeviceArgs::from_uri(url, &[])?;
         let path = url.path();
 
         Ok(Self { path: path.into() })
@@ -60,6 +64,12 @@ impl Device {
     }
 }
 
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "nova-tz://rsa{}", self.path.to_string_lossy())
+    }
+}
+
 #[derive(Debug, Serialize)]
 pub struct Info {
     path: PathBuf,
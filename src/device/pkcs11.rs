@@ -0,0 +1,748 @@
+use crate::{
+    anyhow, bail,
+    device::{
+        test::{self, TestResult},
+        DeviceArgs, Overwrite,
+    },
+    Result,
+};
+use cryptoki::{
+    context::{CInitializeArgs, Pkcs11},
+    error::{Error as Pkcs11Error, RvError},
+    mechanism::{
+        elliptic_curve::{EcKdfType, Ecdh1DeriveParams},
+        Mechanism,
+    },
+    object::{Attribute, AttributeType, KeyType as Pkcs11KeyType, ObjectClass, ObjectHandle},
+    session::{Session, UserType},
+    slot::Slot,
+    types::Version,
+};
+use helium_crypto::{ecc_compact, KeyTag, KeyType, Keypair, PublicKey, Verify};
+use p256::{
+    elliptic_curve::{sec1::ToEncodedPoint, DecompactPoint},
+    FieldBytes,
+};
+use rand::rngs::OsRng;
+use serde::Serialize;
+use sha2::{Digest, Sha256};
+use std::{
+    collections::HashMap,
+    ffi::c_void,
+    fmt, fs,
+    path::{Path, PathBuf},
+    ptr,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+/// The DER encoded object identifier of the NIST P-256 curve, used as the
+/// `CKA_EC_PARAMS` of generated keys.
+const P256_PARAMS: &[u8] = &[0x06, 0x08, 0x2a, 0x86, 0x48, 0xce, 0x3d, 0x03, 0x01, 0x07];
+
+/// The number of attempts to generate a key that can be used as an
+/// ecc_compact key. About half of all P-256 keys can be, so running out of
+/// attempts is practically impossible.
+const GENERATE_ATTEMPTS: usize = 32;
+
+/// The loaded PKCS#11 modules by path. A module is initialized once per
+/// process and finalized when its last context is dropped, so every device
+/// using a module shares its context.
+static MODULES: OnceLock<Mutex<HashMap<PathBuf, Pkcs11>>> = OnceLock::new();
+
+/// A key on a PKCS#11 token, addressed by the module that implements the
+/// token, the slot of the token and the label of the key.
+#[derive(Debug, Clone)]
+pub struct Device {
+    /// The PKCS#11 module path
+    pub module: PathBuf,
+    /// The PKCS#11 slot id of the token, the first slot with an initialized
+    /// token when not given
+    pub slot: Option<u64>,
+    /// The label of the key objects
+    pub label: String,
+    /// The file the user pin is read from
+    pub pin_file: Option<PathBuf>,
+    /// Whether an existing key may be replaced
+    pub overwrite: Overwrite,
+    session: Arc<Mutex<Option<TokenSession>>>,
+}
+
+/// A key held by a PKCS#11 token. The private key can not leave the token,
+/// so signing is done by the token.
+#[derive(Debug)]
+pub struct Key {
+    device: Device,
+    public_key: PublicKey,
+}
+
+/// An open session with the token of a device, and the slot it was opened on.
+struct TokenSession {
+    context: Pkcs11,
+    slot: Slot,
+    session: Session,
+}
+
+impl fmt::Debug for TokenSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TokenSession")
+            .field("slot", &self.slot.id())
+            .finish()
+    }
+}
+
+impl Device {
+    /// Parses a pkcs11 device url of the form
+    /// `pkcs11:///<module>?label=<label>&slot=<slot>&pin-file=<path>`, where
+    /// <module> is the absolute path of the PKCS#11 module, <label> the label
+    /// of the key objects and <slot> the PKCS#11 slot id of the token. Without
+    /// a slot the first slot with an initialized token is used. The user pin
+    /// is read from the pin file, without it the token login is skipped.
+    pub fn from_url(url: &str) -> Result<Self> {
+        let (path, query) = match url.split_once('?') {
+            Some((path, query)) => (path, Some(query)),
+            None => (url, None),
+        };
+        let args = DeviceArgs::from_query(url, query, &["label", "slot", "pin-file"])?;
+        let module = path
+            .strip_prefix("pkcs11://")
+            .ok_or_else(|| anyhow!("invalid pkcs11 url \"{url}\": expected pkcs11:///<module>"))?;
+        if !module.starts_with('/') {
+            bail!(
+                "invalid pkcs11 url \"{url}\": the module path must be absolute, use pkcs11:///<module>"
+            );
+        }
+        let label = args
+            .get_string("label")
+            .filter(|label| !label.is_empty())
+            .ok_or_else(|| anyhow!("invalid pkcs11 url \"{url}\": missing label argument"))?;
+        let slot = args
+            .get_string("slot")
+            .map(|slot| {
+                slot.parse().map_err(|_| {
+                    anyhow!("invalid pkcs11 url \"{url}\": invalid slot id \"{slot}\"")
+                })
+            })
+            .transpose()?;
+        Ok(Self {
+            module: PathBuf::from(module),
+            slot,
+            label,
+            pin_file: args.get_string("pin-file").map(PathBuf::from),
+            overwrite: Overwrite::default(),
+            session: Arc::new(Mutex::new(None)),
+        })
+    }
+
+    /// Loads the module and logs in to the token.
+    pub fn init(&self) -> Result {
+        self.with_session(|_| Ok(()))
+    }
+
+    pub fn get_info(&self) -> Result<Info> {
+        self.with_session(|token| {
+            let info = token.context.get_token_info(token.slot)?;
+            let library = token.context.get_library_info()?;
+            Ok(Info {
+                module: self.module.clone(),
+                slot: token.slot.id(),
+                token: info.label().trim_end().to_string(),
+                manufacturer: info.manufacturer_id().trim_end().to_string(),
+                model: info.model().trim_end().to_string(),
+                serial: info.serial_number().trim_end().to_string(),
+                hardware: version_string(info.hardware_version()),
+                firmware: version_string(info.firmware_version()),
+                library: format!(
+                    "{} {}",
+                    library.library_description().trim_end(),
+                    version_string(library.library_version())
+                ),
+            })
+        })
+    }
+
+    /// Reads the attributes of the private key that keep it on the token.
+    pub fn get_config(&self) -> Result<Config> {
+        self.with_session(|token| {
+            let key = self.find_private_key(&token.session)?;
+            let attributes = token.session.get_attributes(
+                key,
+                &[
+                    AttributeType::Sensitive,
+                    AttributeType::AlwaysSensitive,
+                    AttributeType::Extractable,
+                    AttributeType::NeverExtractable,
+                    AttributeType::Local,
+                ],
+            )?;
+            let mut config = Config {
+                module: self.module.clone(),
+                slot: token.slot.id(),
+                label: self.label.clone(),
+                sensitive: false,
+                always_sensitive: false,
+                extractable: false,
+                never_extractable: false,
+                local: false,
+            };
+            for attribute in attributes {
+                match attribute {
+                    Attribute::Sensitive(value) => config.sensitive = value,
+                    Attribute::AlwaysSensitive(value) => config.always_sensitive = value,
+                    Attribute::Extractable(value) => config.extractable = value,
+                    Attribute::NeverExtractable(value) => config.never_extractable = value,
+                    Attribute::Local(value) => config.local = value,
+                    _ => (),
+                }
+            }
+            Ok(config)
+        })
+    }
+
+    /// Returns the key with the device label, generating it on the token when
+    /// `create` is set. An existing key is only replaced with `--force`.
+    pub fn get_keypair(&self, create: bool) -> Result<Key> {
+        let public_key = self.with_session(|token| {
+            if create {
+                self.generate_key(&token.session)
+            } else {
+                let public = self.find_public_key(&token.session)?;
+                get_public_key(&token.session, public)
+            }
+        })?;
+        Ok(Key {
+            device: self.clone(),
+            public_key,
+        })
+    }
+
+    pub fn provision(&self) -> Result<Key> {
+        self.get_keypair(true)
+    }
+
+    pub fn get_tests(&self) -> Vec<Test> {
+        vec![
+            Test::MinerKey(self.clone()),
+            Test::Sign(self.clone()),
+            Test::Ecdh(self.clone()),
+        ]
+    }
+
+    /// Signs the SHA-256 digest of the data with the private key and returns
+    /// the DER encoded signature.
+    pub fn sign(&self, data: &[u8]) -> Result<Vec<u8>> {
+        let signature = self.with_session(|token| {
+            let key = self.find_private_key(&token.session)?;
+            Ok(token
+                .session
+                .sign(&Mechanism::Ecdsa, key, &Sha256::digest(data))?)
+        })?;
+        let signature = p256::ecdsa::Signature::try_from(signature.as_slice())
+            .map_err(|err| anyhow!("invalid signature from pkcs11 token: {err}"))?;
+        Ok(signature.to_der().as_bytes().to_vec())
+    }
+
+    /// Derives the ECDH shared secret with the given ecc_compact public key.
+    /// The secret is derived as a temporary session object, read and
+    /// destroyed.
+    pub fn ecdh(&self, public_key: &PublicKey) -> Result<Vec<u8>> {
+        let point = uncompressed_point(public_key)?;
+        self.with_session(|token| {
+            let key = self.find_private_key(&token.session)?;
+            let mechanism = Mechanism::Ecdh1Derive(Ecdh1DeriveParams {
+                kdf: EcKdfType::NULL,
+                shared_data_len: 0.into(),
+                shared_data: ptr::null(),
+                public_data_len: (point.len() as u64).into(),
+                public_data: point.as_ptr() as *const c_void,
+            });
+            let template = [
+                Attribute::Class(ObjectClass::SECRET_KEY),
+                Attribute::KeyType(Pkcs11KeyType::GENERIC_SECRET),
+                Attribute::Token(false),
+                Attribute::Sensitive(false),
+                Attribute::Extractable(true),
+                Attribute::ValueLen(32.into()),
+            ];
+            let secret = token.session.derive_key(&mechanism, key, &template)?;
+            let value = token
+                .session
+                .get_attributes(secret, &[AttributeType::Value]);
+            token.session.destroy_object(secret)?;
+            match value?.into_iter().next() {
+                Some(Attribute::Value(value)) => Ok(value),
+                _ => bail!("pkcs11 token did not return the derived secret"),
+            }
+        })
+    }
+
+    fn generate_key(&self, session: &Session) -> Result<PublicKey> {
+        let existing = [
+            find(session, ObjectClass::PRIVATE_KEY, &self.label)?,
+            find(session, ObjectClass::PUBLIC_KEY, &self.label)?,
+        ];
+        if existing.iter().any(Option::is_some) {
+            if !self.overwrite.force {
+                bail!(
+                    "key \"{}\" already exists on the token, use --force to replace it",
+                    self.label
+                );
+            }
+            for object in existing.into_iter().flatten() {
+                session.destroy_object(object)?;
+            }
+        }
+        let label = self.label.as_bytes().to_vec();
+        // The public key can be read, the private key can sign and derive
+        // and never leaves the token
+        let public_template = [
+            Attribute::Token(true),
+            Attribute::Private(false),
+            Attribute::Verify(true),
+            Attribute::KeyType(Pkcs11KeyType::EC),
+            Attribute::EcParams(P256_PARAMS.to_vec()),
+            Attribute::Label(label.clone()),
+        ];
+        let private_template = [
+            Attribute::Token(true),
+            Attribute::Private(true),
+            Attribute::Sensitive(true),
+            Attribute::Extractable(false),
+            Attribute::Sign(true),
+            Attribute::Derive(true),
+            Attribute::KeyType(Pkcs11KeyType::EC),
+            Attribute::Label(label),
+        ];
+        for _ in 0..GENERATE_ATTEMPTS {
+            let (public, private) = session.generate_key_pair(
+                &Mechanism::EccKeyPairGen,
+                &public_template,
+                &private_template,
+            )?;
+            match get_public_key(session, public) {
+                Ok(public_key) => return Ok(public_key),
+                // Not every P-256 key can be used as an ecc_compact key
+                Err(_) => {
+                    session.destroy_object(public)?;
+                    session.destroy_object(private)?;
+                }
+            }
+        }
+        bail!("failed to generate a compact key on the token")
+    }
+
+    fn find_private_key(&self, session: &Session) -> Result<ObjectHandle> {
+        find(session, ObjectClass::PRIVATE_KEY, &self.label)?
+            .ok_or_else(|| anyhow!("private key \"{}\" not found on the token", self.label))
+    }
+
+    fn find_public_key(&self, session: &Session) -> Result<ObjectHandle> {
+        find(session, ObjectClass::PUBLIC_KEY, &self.label)?
+            .ok_or_else(|| anyhow!("public key \"{}\" not found on the token", self.label))
+    }
+
+    /// Runs the given function with the session of the device, opening it
+    /// and logging in on first use.
+    fn with_session<T>(&self, f: impl FnOnce(&TokenSession) -> Result<T>) -> Result<T> {
+        let mut guard = self
+            .session
+            .lock()
+            .map_err(|_| anyhow!("pkcs11 session lock poisoned"))?;
+        let session = match guard.as_mut() {
+            Some(session) => session,
+            None => guard.insert(self.open()?),
+        };
+        f(session)
+    }
+
+    fn open(&self) -> Result<TokenSession> {
+        let context = load_module(&self.module)?;
+        let slot = match self.slot {
+            Some(id) => context
+                .get_slots_with_initialized_token()?
+                .into_iter()
+                .find(|slot| slot.id() == id)
+                .ok_or_else(|| {
+                    anyhow!(
+                        "no initialized token in slot {id} of {}",
+                        self.module.display()
+                    )
+                })?,
+            None => context
+                .get_slots_with_initialized_token()?
+                .into_iter()
+                .next()
+                .ok_or_else(|| anyhow!("no initialized token in {}", self.module.display()))?,
+        };
+        let session = context.open_rw_session(slot)?;
+        if let Some(path) = &self.pin_file {
+            let pin = fs::read_to_string(path)
+                .map_err(|err| anyhow!("failed to read pin file {}: {err}", path.display()))?;
+            let pin = pin.trim_end_matches(['\r', '\n']);
+            match session.login(UserType::User, Some(pin)) {
+                // The login is shared by all sessions with the token
+                Ok(()) | Err(Pkcs11Error::Pkcs11(RvError::UserAlreadyLoggedIn)) => (),
+                Err(err) => bail!("failed to log in to the pkcs11 token: {err}"),
+            }
+        }
+        Ok(TokenSession {
+            context,
+            slot,
+            session,
+        })
+    }
+}
+
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "pkcs11://{}", self.module.display())?;
+        let mut args = vec![("label", self.label.clone())];
+        if let Some(slot) = self.slot {
+            args.push(("slot", slot.to_string()));
+        }
+        if let Some(pin_file) = &self.pin_file {
+            args.push(("pin-file", pin_file.to_string_lossy().into_owned()));
+        }
+        let query = serde_urlencoded::to_string(args).map_err(|_| fmt::Error)?;
+        write!(f, "?{query}")
+    }
+}
+
+impl Key {
+    pub fn public_key(&self) -> &PublicKey {
+        &self.public_key
+    }
+
+    pub fn sign(&self, msg: &[u8]) -> Result<Vec<u8>> {
+        self.device.sign(msg)
+    }
+}
+
+/// Returns the context of the module at the given path, loading and
+/// initializing the module on first use.
+fn load_module(path: &Path) -> Result<Pkcs11> {
+    let mut modules = MODULES
+        .get_or_init(Default::default)
+        .lock()
+        .map_err(|_| anyhow!("pkcs11 module lock poisoned"))?;
+    if let Some(context) = modules.get(path) {
+        return Ok(context.clone());
+    }
+    let mut context = Pkcs11::new(path)
+        .map_err(|err| anyhow!("failed to load pkcs11 module {}: {err}", path.display()))?;
+    context
+        .initialize(CInitializeArgs::OsThreads)
+        .map_err(|err| {
+            anyhow!(
+                "failed to initialize pkcs11 module {}: {err}",
+                path.display()
+            )
+        })?;
+    modules.insert(path.to_path_buf(), context.clone());
+    Ok(context)
+}
+
+/// Finds the object of the given class with the given label.
+fn find(session: &Session, class: ObjectClass, label: &str) -> Result<Option<ObjectHandle>> {
+    let objects = session.find_objects(&[
+        Attribute::Class(class),
+        Attribute::Label(label.as_bytes().to_vec()),
+    ])?;
+    Ok(objects.into_iter().next())
+}
+
+/// Reads the public key object as an ecc_compact public key.
+fn get_public_key(session: &Session, object: ObjectHandle) -> Result<PublicKey> {
+    let point = match session
+        .get_attributes(object, &[AttributeType::EcPoint])?
+        .into_iter()
+        .next()
+    {
+        Some(Attribute::EcPoint(point)) => point,
+        _ => bail!("pkcs11 public key has no ec point"),
+    };
+    let public_key = ecc_compact::PublicKey::try_from(decode_ec_point(&point))
+        .map_err(|err| anyhow!("unsupported token key: {err}"))?;
+    Ok(public_key.into())
+}
+
+fn version_string(version: Version) -> String {
+    format!("{}.{}", version.major(), version.minor())
+}
+
+/// Decodes a `CKA_EC_POINT` value, which is a DER octet string holding the
+/// uncompressed point, or the raw point for some tokens.
+fn decode_ec_point(data: &[u8]) -> &[u8] {
+    match data {
+        [0x04, len, point @ ..] if *len as usize == point.len() && point.first() == Some(&0x04) => {
+            point
+        }
+        _ => data,
+    }
+}
+
+/// Returns the uncompressed point of an ecc_compact public key.
+fn uncompressed_point(public_key: &PublicKey) -> Result<Vec<u8>> {
+    if public_key.key_type() != KeyType::EccCompact {
+        bail!("pkcs11 ecdh needs an ecc_compact public key");
+    }
+    let bytes = public_key.to_vec();
+    let point: Option<p256::AffinePoint> =
+        p256::AffinePoint::decompact(FieldBytes::from_slice(&bytes[1..])).into();
+    let point = point.ok_or_else(|| anyhow!("invalid ecc_compact public key"))?;
+    Ok(point.to_encoded_point(false).as_bytes().to_vec())
+}
+
+#[derive(Debug, Serialize)]
+pub struct Info {
+    module: PathBuf,
+    slot: u64,
+    token: String,
+    manufacturer: String,
+    model: String,
+    serial: String,
+    hardware: String,
+    firmware: String,
+    library: String,
+}
+
+impl Info {
+    pub fn serial(&self) -> String {
+        self.serial.clone()
+    }
+}
+
+#[derive(Debug, Serialize)]
+pub struct Config {
+    module: PathBuf,
+    slot: u64,
+    label: String,
+    sensitive: bool,
+    always_sensitive: bool,
+    extractable: bool,
+    never_extractable: bool,
+    local: bool,
+}
+
+#[derive(Debug)]
+pub enum Test {
+    MinerKey(Device),
+    Sign(Device),
+    Ecdh(Device),
+}
+
+impl fmt::Display for Test {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::MinerKey(device) => f.write_fmt(format_args!("miner_key({})", device.label)),
+            Self::Sign(device) => f.write_fmt(format_args!("sign({})", device.label)),
+            Self::Ecdh(device) => f.write_fmt(format_args!("ecdh({})", device.label)),
+        }
+    }
+}
+
+impl Test {
+    pub fn run(&self) -> TestResult {
+        match self {
+            Self::MinerKey(device) => check_miner_key(device),
+            Self::Sign(device) => check_sign(device),
+            Self::Ecdh(device) => check_ecdh(device),
+        }
+    }
+}
+
+fn check_miner_key(device: &Device) -> TestResult {
+    let key = device.get_keypair(false)?;
+    test::pass(key.public_key()).into()
+}
+
+fn check_sign(device: &Device) -> TestResult {
+    const DATA: &[u8] = b"hello world";
+    let key = device.get_keypair(false)?;
+    let signature = key.sign(DATA)?;
+    key.public_key().verify(DATA, &signature)?;
+    test::pass("ok").into()
+}
+
+fn check_ecdh(device: &Device) -> TestResult {
+    let key = device.get_keypair(false)?;
+    let other_keypair = Keypair::generate(
+        KeyTag {
+            network: key.public_key().network,
+            key_type: KeyType::EccCompact,
+        },
+        &mut OsRng,
+    );
+    let token_shared_secret = device.ecdh(other_keypair.public_key())?;
+    let other_shared_secret = other_keypair.ecdh(key.public_key())?;
+
+    if token_shared_secret.as_slice() != other_shared_secret.as_bytes().as_slice() {
+        return test::expected(
+            format!("{:02x?}", token_shared_secret),
+            format!("{:02x?}", other_shared_secret.as_bytes().as_slice()),
+        )
+        .into();
+    }
+    test::pass("ok").into()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{env, process};
+
+    /// The paths SoftHSM is installed at by common distributions.
+    const SOFTHSM_MODULES: &[&str] = &[
+        "/usr/lib/softhsm/libsofthsm2.so",
+        "/usr/lib/x86_64-linux-gnu/softhsm/libsofthsm2.so",
+        "/usr/lib64/pkcs11/libsofthsm2.so",
+        "/usr/local/lib/softhsm/libsofthsm2.so",
+    ];
+
+    const SO_PIN: &str = "12345678";
+    const USER_PIN: &str = "87654321";
+
+    fn softhsm_module() -> Option<PathBuf> {
+        env::var_os("GW_MFR_SOFTHSM_MODULE")
+            .map(PathBuf::from)
+            .or_else(|| {
+                SOFTHSM_MODULES
+                    .iter()
+                    .map(PathBuf::from)
+                    .find(|path| path.exists())
+            })
+    }
+
+    /// Points SoftHSM at a new token directory and initializes a token in
+    /// it, returning the pin file for the token.
+    fn softhsm_token(module: &Path, dir: &Path) -> Result<PathBuf> {
+        let tokens = dir.join("tokens");
+        fs::create_dir_all(&tokens)?;
+        let config = dir.join("softhsm2.conf");
+        fs::write(
+            &config,
+            format!(
+                "directories.tokendir = {}\nobjectstore.backend = file\nlog.level = ERROR\n",
+                tokens.display()
+            ),
+        )?;
+        env::set_var("SOFTHSM2_CONF", &config);
+
+        let context = load_module(module)?;
+        let slot = *context
+            .get_slots_with_token()?
+            .first()
+            .ok_or_else(|| anyhow!("no softhsm slot"))?;
+        context.init_token(slot, SO_PIN, "gateway-mfr")?;
+        // SoftHSM moves an initialized token to a new slot, so look it up
+        // again
+        let slot = *context
+            .get_slots_with_initialized_token()?
+            .first()
+            .ok_or_else(|| anyhow!("no initialized softhsm token"))?;
+        let session = context.open_rw_session(slot)?;
+        session.login(UserType::So, Some(SO_PIN))?;
+        session.init_pin(USER_PIN)?;
+        session.logout()?;
+
+        let pin_file = dir.join("pin");
+        fs::write(&pin_file, format!("{USER_PIN}\n"))?;
+        Ok(pin_file)
+    }
+
+    #[test]
+    fn url() {
+        let device = Device::from_url(
+            "pkcs11:///usr/lib/softhsm/libsofthsm2.so?label=miner&slot=1&pin-file=/etc/pin",
+        )
+        .expect("pkcs11 url");
+        assert_eq!(device.module, Path::new("/usr/lib/softhsm/libsofthsm2.so"));
+        assert_eq!(device.slot, Some(1));
+        assert_eq!(device.label, "miner");
+        assert_eq!(device.pin_file.as_deref(), Some(Path::new("/etc/pin")));
+        let parsed = Device::from_url(&device.to_string()).expect("displayed url");
+        assert_eq!(parsed.to_string(), device.to_string());
+
+        let device = Device::from_url("pkcs11:///usr/lib/softhsm/libsofthsm2.so?label=miner")
+            .expect("pkcs11 url without slot");
+        assert_eq!(device.slot, None);
+        assert_eq!(
+            device.to_string(),
+            "pkcs11:///usr/lib/softhsm/libsofthsm2.so?label=miner"
+        );
+
+        assert!(Device::from_url("pkcs11:///usr/lib/softhsm/libsofthsm2.so").is_err());
+        assert!(Device::from_url("pkcs11://libsofthsm2.so?label=miner").is_err());
+        assert!(Device::from_url("pkcs11:///lib.so?label=miner&pin=1234").is_err());
+        assert!(Device::from_url("pkcs11:///lib.so?label=miner&slot=first").is_err());
+    }
+
+    #[test]
+    fn ec_point() {
+        let point = [0x04; 65];
+        let mut der = vec![0x04, 65];
+        der.extend_from_slice(&point);
+        assert_eq!(decode_ec_point(&der), &point);
+        assert_eq!(decode_ec_point(&point), &point);
+    }
+
+    #[test]
+    fn missing_module() {
+        let device =
+            Device::from_url("pkcs11:///nonexistent/libpkcs11.so?label=miner").expect("pkcs11 url");
+        assert!(device.init().is_err());
+    }
+
+    /// Runs the device against a SoftHSM token. Run with `--ignored` where
+    /// SoftHSM is installed, or point `GW_MFR_SOFTHSM_MODULE` at the module.
+    #[test]
+    #[ignore = "needs SoftHSM"]
+    fn softhsm() {
+        let module = softhsm_module().expect("softhsm module not found");
+        let dir = env::temp_dir().join(format!("gateway_mfr-softhsm-{}", process::id()));
+        let pin_file = softhsm_token(&module, &dir).expect("softhsm token");
+        let url = format!(
+            "pkcs11://{}?label=miner&pin-file={}",
+            module.display(),
+            pin_file.display()
+        );
+        let device: crate::Device = url.parse().expect("pkcs11 device");
+        device.init().expect("init");
+
+        assert!(device.load_keypair().is_err());
+        let key = device.provision().expect("provision");
+        assert!(device.provision().is_err(), "replaced without --force");
+        assert_eq!(
+            device.load_keypair().expect("key").public_key(),
+            key.public_key()
+        );
+        let replaced = device
+            .clone()
+            .with_overwrite(Overwrite {
+                force: true,
+                backup: false,
+            })
+            .provision()
+            .expect("forced provision");
+        assert_ne!(replaced.public_key(), key.public_key());
+
+        let info = serde_json::to_value(device.get_info().expect("info")).expect("info json");
+        assert_eq!(info["token"], "gateway-mfr");
+        let config = serde_json::to_value(device.get_config().expect("config")).expect("json");
+        assert_eq!(config["sensitive"], true);
+        assert_eq!(config["extractable"], false);
+
+        // A second device on the same module keeps working when the first
+        // one is dropped
+        let slot = info["slot"].as_u64().expect("slot id");
+        let second: crate::Device = format!("{url}&slot={slot}")
+            .parse()
+            .expect("pkcs11 device with slot");
+        drop(device);
+        for test in second.get_tests() {
+            let outcome = test.run().expect("test");
+            assert!(outcome.passed(), "{test}: {outcome:?}");
+        }
+        drop(second);
+        fs::remove_dir_all(dir).expect("remove softhsm dir");
+    }
+}
@@ -1,41 +1,201 @@
 use http::Uri;
-use std::fmt;
+use rand::{rngs::OsRng, RngCore};
+use std::{
+    collections::HashMap,
+    env,
+    ffi::{c_void, CStr, CString},
+    fmt,
+    fs::{self, DirBuilder, OpenOptions},
+    io::Write,
+    ops::RangeInclusive,
+    os::{
+        raw::c_char,
+        unix::fs::{DirBuilderExt, OpenOptionsExt},
+    },
+    path::PathBuf,
+    process, ptr,
+    sync::{Mutex, Once},
+};
 
 use serde::Serialize;
 
 use helium_crypto::{tpm, KeyTag, KeyType, Keypair, Network, Sign, Verify};
+use tss2::{
+    Fapi_CreateKey, Fapi_Delete, Fapi_Finalize, Fapi_Free, Fapi_GetEsysBlob, Fapi_GetInfo,
+    Fapi_GetTpmBlobs, Fapi_Initialize, Fapi_Provision, FAPI_CONTEXT, TSS2_RC_SUCCESS,
+};
 
 use crate::{
-    device::test::{self, TestResult},
+    anyhow, bail,
+    device::{
+        test::{self, TestResult},
+        DeviceArgs, Overwrite,
+    },
     Result,
 };
 
+/// The environment variable FAPI reads the path of its config file from
+const FAPI_CONFIG_ENV: &str = "TSS2_FAPICONF";
+/// The FAPI config file used when `TSS2_FAPICONF` is not set
+const DEFAULT_FAPI_CONFIG: &str = "/etc/tpm2-tss/fapi-config.json";
+
+/// The private directory with the FAPI config written by `Device::init`,
+/// which is removed when the process exits
+static FAPI_CONFIG_DIR: Mutex<Option<PathBuf>> = Mutex::new(None);
+static FAPI_CONFIG_CLEANUP: Once = Once::new();
+
+/// The FAPI key type of created keys. Keys are unrestricted so they can both
+/// sign and be used for ECDH, and have no authorization value so they are
+/// exempt from dictionary attack lockout.
+const KEY_TYPE: &str = "sign,decrypt,noDa";
+
+/// The range of TPM persistent object handles
+const PERSISTENT_HANDLES: RangeInclusive<u32> = 0x8100_0000..=0x81ff_ffff;
+
+/// The FAPI return codes for a key path that does not exist
+const TSS2_FAPI_RC_PATH_NOT_FOUND: u32 = 0x6001a;
+const TSS2_FAPI_RC_KEY_NOT_FOUND: u32 = 0x60022;
+
+/// The ESYS blob of a persistent key is a serialized ESYS_TR, which starts
+/// with the handle of the key. Other keys have a saved context instead.
+const FAPI_ESYSBLOB_DESERIALIZE: u8 = 2;
+
 #[derive(Debug, Clone)]
 pub struct Device {
     /// TPM key path
     pub path: String,
+    /// The persistent handle to create the key at
+    pub handle: Option<u32>,
+    /// The TCTI to reach the TPM with, instead of the one in the FAPI config
+    pub tcti: Option<String>,
+    /// Whether an existing key may be replaced
+    pub overwrite: Overwrite,
 }
 
 impl Device {
     /// Parses a tpm device url of the form `tpm://tpm/<key_path>`,
-    /// where <key_path> is the path to TPM KEY
+    /// where <key_path> is the FAPI path of the key, for example
+    /// `/HS/SRK/miner`. A `handle=<handle>` argument makes created keys
+    /// persistent at the given handle, like `0x81000002`. A `tcti=<tcti>`
+    /// argument selects the TCTI, like `device:/dev/tpmrm0`, `tabrmd` or
+    /// `swtpm:host=localhost,port=2321`. The host is always `tpm`, the TPM
+    /// itself is selected with the TCTI.
     pub fn from_url(url: &Uri) -> Result<Self> {
+        match url.host() {
+            Some("tpm") => (),
+            Some(host) => bail!(
+                "invalid tpm url \"{url}\": unknown host \"{host}\", use tpm://tpm/<key_path> and a tcti argument to select the tpm"
+            ),
+            None => bail!("invalid tpm url \"{url}\": missing host, use tpm://tpm/<key_path>"),
+        }
+        let args = DeviceArgs::from_uri(url, &["handle", "tcti"])?;
         let path = url.path();
+        let handle = args
+            .get_string("handle")
+            .map(|handle| parse_handle(&handle))
+            .transpose()?;
+        let tcti = args
+            .get_string("tcti")
+            .map(|tcti| parse_tcti(&tcti))
+            .transpose()?;
 
         Ok(Self {
             path: path.to_string(),
+            handle,
+            tcti,
+            overwrite: Overwrite::default(),
         })
     }
 
+    /// Points FAPI at the TCTI from the device url. FAPI only reads the TCTI
+    /// from its config file, so this writes a copy of the FAPI config with
+    /// the TCTI replaced and uses it for all FAPI contexts of this process.
+    /// The copy is kept in a new directory only the current user can access,
+    /// and removed when the process exits.
+    pub fn init(&self) -> Result {
+        let Some(tcti) = &self.tcti else {
+            return Ok(());
+        };
+        let base = env::var_os(FAPI_CONFIG_ENV)
+            .map(PathBuf::from)
+            .unwrap_or_else(|| PathBuf::from(DEFAULT_FAPI_CONFIG));
+        let contents = fs::read_to_string(&base)
+            .map_err(|err| anyhow!("failed to read fapi config {}: {err}", base.display()))?;
+        let mut config: serde_json::Value = serde_json::from_str(&contents)
+            .map_err(|err| anyhow!("invalid fapi config {}: {err}", base.display()))?;
+        let Some(fields) = config.as_object_mut() else {
+            bail!("invalid fapi config {}: expected an object", base.display());
+        };
+        fields.insert("tcti".to_string(), tcti.clone().into());
+
+        let dir = env::temp_dir().join(format!(
+            "gateway_mfr-fapi-{}-{:016x}",
+            process::id(),
+            OsRng.next_u64()
+        ));
+        // Creating the directory fails rather than reusing one that already
+        // exists, so nobody else can have access to it
+        DirBuilder::new()
+            .mode(0o700)
+            .create(&dir)
+            .map_err(|err| anyhow!("failed to create fapi config dir {}: {err}", dir.display()))?;
+        set_fapi_config_dir(dir.clone());
+
+        let path = dir.join("fapi-config.json");
+        let contents = serde_json::to_vec_pretty(&config)?;
+        OpenOptions::new()
+            .write(true)
+            .create_new(true)
+            .mode(0o600)
+            .open(&path)
+            .and_then(|mut file| file.write_all(&contents))
+            .map_err(|err| anyhow!("failed to write fapi config {}: {err}", path.display()))?;
+        env::set_var(FAPI_CONFIG_ENV, &path);
+        Ok(())
+    }
+
+    /// Reads the manufacturer, vendor, firmware version and spec revision
+    /// from the fixed properties of the TPM.
     pub fn get_info(&self) -> Result<Info> {
+        let info = Fapi::new()?.get_info()?;
+        let mut properties = HashMap::new();
+        tpm_properties(&info, &mut properties);
+        let property = |name: &str| properties.get(name).and_then(property_u32);
+        let firmware = match (
+            property("TPM2_PT_FIRMWARE_VERSION_1"),
+            property("TPM2_PT_FIRMWARE_VERSION_2"),
+        ) {
+            (Some(v1), Some(v2)) => Some(format!(
+                "{}.{}.{}.{}",
+                v1 >> 16,
+                v1 & 0xffff,
+                v2 >> 16,
+                v2 & 0xffff
+            )),
+            _ => None,
+        };
         Ok(Info {
             path: self.path.clone(),
+            handle: read_handle(&self.path)?.map(|handle| format!("{handle:#010x}")),
+            manufacturer: property_string(&properties, &["TPM2_PT_MANUFACTURER"]),
+            vendor: property_string(
+                &properties,
+                &[
+                    "TPM2_PT_VENDOR_STRING_1",
+                    "TPM2_PT_VENDOR_STRING_2",
+                    "TPM2_PT_VENDOR_STRING_3",
+                    "TPM2_PT_VENDOR_STRING_4",
+                ],
+            ),
+            firmware,
+            spec_revision: property("TPM2_PT_REVISION")
+                .map(|revision| format!("{}.{:02}", revision / 100, revision % 100)),
         })
     }
 
     pub fn get_keypair(&self, create: bool) -> Result<Keypair> {
         if create {
-            panic!("not supported")
+            self.create_key()?;
         }
 
         let keypair = tpm::Keypair::from_key_path(Network::MainNet, self.path.as_str())
@@ -44,17 +204,73 @@ impl Device {
     }
 
     pub fn provision(&self) -> Result<Keypair> {
-        panic!("not supported")
+        self.get_keypair(true)
     }
 
+    /// Creates a P-256 key at the key path, replacing an existing key only
+    /// when forced. The parent of the key follows from the key path, so
+    /// `/HS/SRK/miner` is created under the storage root key of the storage
+    /// hierarchy. Without a handle FAPI keeps the key blob in its keystore.
+    ///
+    /// FAPI can not create a key at an existing path or move a key, so a
+    /// replaced key is deleted before the new one is created and can not be
+    /// backed up.
+    fn create_key(&self) -> Result {
+        if self.overwrite.backup {
+            bail!(
+                "tpm key {} can not be backed up, replace it with --force only",
+                self.path
+            );
+        }
+        let mut fapi = Fapi::new()?;
+        // Sets up the storage root key and keystore on first use, and fails
+        // when that was done before
+        let provisioned = fapi.provision();
+        let fail = |err: crate::Error| match &provisioned {
+            Ok(()) => anyhow!("failed to create tpm key {}: {err}", self.path),
+            Err(provision_err) => anyhow!(
+                "failed to create tpm key {}: {err}, provisioning fapi failed: {provision_err}",
+                self.path
+            ),
+        };
+        if self.overwrite.force {
+            // FAPI refuses to create a key at an existing path
+            fapi.delete(&self.path).map_err(fail)?;
+        } else if fapi.get_public(&self.path).map_err(fail)?.is_some() {
+            bail!(
+                "tpm key {} already exists, use --force to replace it",
+                self.path
+            );
+        }
+        let key_type = match self.handle {
+            Some(handle) => format!("{KEY_TYPE},{handle:#010x}"),
+            None => KEY_TYPE.to_string(),
+        };
+        fapi.create_key(&self.path, &key_type).map_err(fail)
+    }
+
+    /// Reads the algorithm, curve and object attributes of the key from its
+    /// public area.
     pub fn get_config(&self) -> Result<Config> {
+        let public = read_public(&self.path)?;
         Ok(Config {
             path: self.path.clone(),
+            tcti: self.tcti.clone(),
+            handle: read_handle(&self.path)?.map(|handle| format!("{handle:#010x}")),
+            algorithm: alg_name(public.key_type),
+            name_alg: alg_name(public.name_alg),
+            curve: public.curve.map(curve_name),
+            attributes: ATTRIBUTES
+                .iter()
+                .filter(|(bit, _)| public.attributes & bit != 0)
+                .map(|(_, name)| *name)
+                .collect(),
         })
     }
 
     pub fn get_tests(&self) -> Vec<Test> {
         vec![
+            Test::KeyAttributes(self.path.clone()),
             Test::MinerKey(self.path.clone()),
             Test::Sign(self.path.clone()),
             Test::Ecdh(self.path.clone()),
@@ -62,18 +278,431 @@ impl Device {
     }
 }
 
+impl fmt::Display for Device {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "tpm://tpm{}", self.path)?;
+        let mut args = vec![];
+        if let Some(handle) = self.handle {
+            args.push(("handle", format!("{handle:#010x}")));
+        }
+        if let Some(tcti) = &self.tcti {
+            args.push(("tcti", tcti.clone()));
+        }
+        if !args.is_empty() {
+            let query = serde_urlencoded::to_string(args).map_err(|_| fmt::Error)?;
+            write!(f, "?{query}")?;
+        }
+        Ok(())
+    }
+}
+
+/// Remembers the directory of the FAPI config for removal at exit, removing
+/// the directory of an earlier config right away.
+fn set_fapi_config_dir(dir: PathBuf) {
+    FAPI_CONFIG_CLEANUP.call_once(|| unsafe {
+        libc::atexit(remove_fapi_config_dir);
+    });
+    let mut config_dir = FAPI_CONFIG_DIR
+        .lock()
+        .unwrap_or_else(|err| err.into_inner());
+    if let Some(previous) = config_dir.replace(dir) {
+        let _ = fs::remove_dir_all(previous);
+    }
+}
+
+extern "C" fn remove_fapi_config_dir() {
+    let dir = FAPI_CONFIG_DIR
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .take();
+    if let Some(dir) = dir {
+        let _ = fs::remove_dir_all(dir);
+    }
+}
+
+fn parse_handle(s: &str) -> Result<u32> {
+    let handle = s
+        .strip_prefix("0x")
+        .and_then(|hex| u32::from_str_radix(hex, 16).ok())
+        .ok_or_else(|| {
+            anyhow!("invalid tpm handle \"{s}\", expected a hex handle like 0x81000002")
+        })?;
+    if !PERSISTENT_HANDLES.contains(&handle) {
+        bail!("tpm handle {s} is not a persistent handle, expected 0x81000000 to 0x81ffffff");
+    }
+    Ok(handle)
+}
+
+/// Checks that a TCTI is of the form `<name>[:<config>]`.
+fn parse_tcti(s: &str) -> Result<String> {
+    let name = s.split_once(':').map_or(s, |(name, _)| name);
+    if name.is_empty()
+        || !name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        bail!("invalid tcti \"{s}\", expected <name>[:<config>] like device:/dev/tpmrm0, tabrmd or swtpm:host=localhost,port=2321");
+    }
+    Ok(s.to_string())
+}
+
+/// A FAPI context, which is finalized when dropped. This is separate from the
+/// context helium-crypto uses, and must be dropped before a key is loaded so
+/// TCTIs that allow only one connection keep working.
+struct Fapi(*mut FAPI_CONTEXT);
+
+impl Fapi {
+    fn new() -> Result<Self> {
+        let mut context: *mut FAPI_CONTEXT = ptr::null_mut();
+        check_rc("Fapi_Initialize", unsafe {
+            Fapi_Initialize(&mut context, ptr::null_mut())
+        })?;
+        Ok(Self(context))
+    }
+
+    fn provision(&mut self) -> Result {
+        check_rc("Fapi_Provision", unsafe {
+            Fapi_Provision(self.0, ptr::null_mut(), ptr::null_mut(), ptr::null_mut())
+        })
+    }
+
+    /// Deletes the key at the given path, returning whether there was one.
+    fn delete(&mut self, path: &str) -> Result<bool> {
+        let path = c_string(path)?;
+        let rc = unsafe { Fapi_Delete(self.0, path.as_ptr()) };
+        if is_not_found(rc) {
+            return Ok(false);
+        }
+        check_rc("Fapi_Delete", rc)?;
+        Ok(true)
+    }
+
+    fn create_key(&mut self, path: &str, key_type: &str) -> Result {
+        let path = c_string(path)?;
+        let key_type = c_string(key_type)?;
+        check_rc("Fapi_CreateKey", unsafe {
+            Fapi_CreateKey(
+                self.0,
+                path.as_ptr(),
+                key_type.as_ptr(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        })
+    }
+
+    /// Returns the ESYS blob type and data of the key at the given path, or
+    /// `None` when there is no key at the path.
+    fn get_esys_blob(&mut self, path: &str) -> Result<Option<(u8, Vec<u8>)>> {
+        let path = c_string(path)?;
+        let mut blob_type: u8 = 0;
+        let mut data: *mut u8 = ptr::null_mut();
+        let mut length: usize = 0;
+        let rc = unsafe {
+            Fapi_GetEsysBlob(
+                self.0,
+                path.as_ptr(),
+                &mut blob_type,
+                &mut data,
+                &mut length,
+            )
+        };
+        if is_not_found(rc) {
+            return Ok(None);
+        }
+        check_rc("Fapi_GetEsysBlob", rc)?;
+        let blob = unsafe { std::slice::from_raw_parts(data, length) }.to_vec();
+        unsafe { Fapi_Free(data as *mut c_void) };
+        Ok(Some((blob_type, blob)))
+    }
+
+    fn get_info(&mut self) -> Result<serde_json::Value> {
+        let mut info: *mut c_char = ptr::null_mut();
+        check_rc("Fapi_GetInfo", unsafe { Fapi_GetInfo(self.0, &mut info) })?;
+        let json = unsafe { CStr::from_ptr(info) }
+            .to_string_lossy()
+            .into_owned();
+        unsafe { Fapi_Free(info as *mut c_void) };
+        serde_json::from_str(&json).map_err(|err| anyhow!("invalid fapi info: {err}"))
+    }
+
+    /// Returns the marshaled TPM2B_PUBLIC of the key at the given path, or
+    /// `None` when there is no key at the path.
+    fn get_public(&mut self, path: &str) -> Result<Option<Vec<u8>>> {
+        let path = c_string(path)?;
+        let mut public: *mut u8 = ptr::null_mut();
+        let mut public_size: usize = 0;
+        let rc = unsafe {
+            Fapi_GetTpmBlobs(
+                self.0,
+                path.as_ptr(),
+                &mut public,
+                &mut public_size,
+                ptr::null_mut(),
+                ptr::null_mut(),
+                ptr::null_mut(),
+            )
+        };
+        if is_not_found(rc) {
+            return Ok(None);
+        }
+        check_rc("Fapi_GetTpmBlobs", rc)?;
+        let data = unsafe { std::slice::from_raw_parts(public, public_size) }.to_vec();
+        unsafe { Fapi_Free(public as *mut c_void) };
+        Ok(Some(data))
+    }
+}
+
+impl Drop for Fapi {
+    fn drop(&mut self) {
+        unsafe { Fapi_Finalize(&mut self.0) }
+    }
+}
+
+fn c_string(s: &str) -> Result<CString> {
+    CString::new(s).map_err(|_| anyhow!("invalid tpm path \"{s}\""))
+}
+
+fn is_not_found(rc: u32) -> bool {
+    matches!(rc, TSS2_FAPI_RC_PATH_NOT_FOUND | TSS2_FAPI_RC_KEY_NOT_FOUND)
+}
+
+fn check_rc(function: &str, rc: u32) -> Result {
+    if rc != TSS2_RC_SUCCESS {
+        bail!("{function} returned error code {rc:#x}");
+    }
+    Ok(())
+}
+
+/// Collects the tagged TPM properties in the output of Fapi_GetInfo by name.
+fn tpm_properties(value: &serde_json::Value, properties: &mut HashMap<String, serde_json::Value>) {
+    match value {
+        serde_json::Value::Object(fields) => {
+            if let (Some(serde_json::Value::String(property)), Some(value)) =
+                (fields.get("property"), fields.get("value"))
+            {
+                properties.insert(property.clone(), value.clone());
+            }
+            for value in fields.values() {
+                tpm_properties(value, properties);
+            }
+        }
+        serde_json::Value::Array(values) => {
+            for value in values {
+                tpm_properties(value, properties);
+            }
+        }
+        _ => (),
+    }
+}
+
+fn property_u32(value: &serde_json::Value) -> Option<u32> {
+    match value {
+        serde_json::Value::Number(number) => number.as_u64().and_then(|v| u32::try_from(v).ok()),
+        serde_json::Value::String(s) => match s.strip_prefix("0x") {
+            Some(hex) => u32::from_str_radix(hex, 16).ok(),
+            None => s.parse().ok(),
+        },
+        _ => None,
+    }
+}
+
+/// Joins properties that each hold up to four ASCII characters, like the
+/// manufacturer and vendor strings. Properties FAPI already decoded to text
+/// are used as is.
+fn property_string(
+    properties: &HashMap<String, serde_json::Value>,
+    names: &[&str],
+) -> Option<String> {
+    let mut result = String::new();
+    for value in names.iter().filter_map(|name| properties.get(*name)) {
+        match (property_u32(value), value) {
+            (Some(chars), _) => result.extend(
+                chars
+                    .to_be_bytes()
+                    .into_iter()
+                    .filter(|c| c.is_ascii_graphic() || *c == b' ')
+                    .map(char::from),
+            ),
+            (None, serde_json::Value::String(s)) => result.push_str(s),
+            _ => (),
+        }
+    }
+    let result = result.trim();
+    (!result.is_empty()).then(|| result.to_string())
+}
+
+const TPM2_ALG_ECC: u16 = 0x0023;
+const TPM2_ALG_ECDAA: u16 = 0x001a;
+const TPM2_ALG_NULL: u16 = 0x0010;
+
+/// The object attributes reported in the key config
+const ATTRIBUTES: &[(u32, &str)] = &[
+    (0x0000_0002, "fixed_tpm"),
+    (0x0000_0004, "st_clear"),
+    (0x0000_0010, "fixed_parent"),
+    (0x0000_0020, "sensitive_data_origin"),
+    (0x0000_0040, "user_with_auth"),
+    (0x0000_0080, "admin_with_policy"),
+    (0x0000_0400, "no_da"),
+    (0x0000_0800, "encrypted_duplication"),
+    (0x0001_0000, "restricted"),
+    (0x0002_0000, "decrypt"),
+    (0x0004_0000, "sign"),
+];
+
+fn has_attribute(attributes: u32, name: &str) -> bool {
+    ATTRIBUTES
+        .iter()
+        .any(|(bit, attribute)| *attribute == name && attributes & bit != 0)
+}
+
+fn alg_name(alg: u16) -> String {
+    match alg {
+        0x0001 => "rsa".to_string(),
+        0x0004 => "sha1".to_string(),
+        0x0008 => "keyedhash".to_string(),
+        0x000b => "sha256".to_string(),
+        0x000c => "sha384".to_string(),
+        0x000d => "sha512".to_string(),
+        TPM2_ALG_NULL => "null".to_string(),
+        TPM2_ALG_ECC => "ecc".to_string(),
+        0x0025 => "symcipher".to_string(),
+        other => format!("{other:#06x}"),
+    }
+}
+
+fn curve_name(curve: u16) -> String {
+    match curve {
+        0x0003 => "nist_p256".to_string(),
+        0x0004 => "nist_p384".to_string(),
+        0x0005 => "nist_p521".to_string(),
+        0x0010 => "bn_p256".to_string(),
+        0x0020 => "sm2_p256".to_string(),
+        other => format!("{other:#06x}"),
+    }
+}
+
+/// The parts of a key's TPMT_PUBLIC area reported in its config.
+struct PublicArea {
+    key_type: u16,
+    name_alg: u16,
+    attributes: u32,
+    curve: Option<u16>,
+}
+
+fn read_public(path: &str) -> Result<PublicArea> {
+    let data = Fapi::new()?
+        .get_public(path)?
+        .ok_or_else(|| anyhow!("tpm key {path} not found"))?;
+    parse_public(&data).map_err(|err| anyhow!("invalid public area for tpm key {path}: {err}"))
+}
+
+/// Reads the persistent handle of the key at the given path from its ESYS
+/// blob. Keys without a handle, and missing keys, have none.
+fn read_handle(path: &str) -> Result<Option<u32>> {
+    match Fapi::new()?.get_esys_blob(path)? {
+        Some((blob_type, blob)) => parse_esys_handle(blob_type, &blob)
+            .map_err(|err| anyhow!("invalid esys blob for tpm key {path}: {err}")),
+        None => Ok(None),
+    }
+}
+
+/// Returns the handle of a serialized ESYS_TR if it is a persistent handle.
+fn parse_esys_handle(blob_type: u8, mut data: &[u8]) -> Result<Option<u32>> {
+    if blob_type != FAPI_ESYSBLOB_DESERIALIZE {
+        return Ok(None);
+    }
+    let handle = read_u32(&mut data)?;
+    Ok(PERSISTENT_HANDLES.contains(&handle).then_some(handle))
+}
+
+/// Parses a marshaled TPM2B_PUBLIC up to the curve of ECC keys.
+fn parse_public(mut data: &[u8]) -> Result<PublicArea> {
+    let data = &mut data;
+    let _size = read_u16(data)?;
+    let key_type = read_u16(data)?;
+    let name_alg = read_u16(data)?;
+    let attributes = read_u32(data)?;
+    let policy_size = read_u16(data)?;
+    read_bytes(data, policy_size as usize)?;
+    let curve = if key_type == TPM2_ALG_ECC {
+        // The symmetric definition has a key size and mode unless it is null
+        if read_u16(data)? != TPM2_ALG_NULL {
+            read_bytes(data, 4)?;
+        }
+        // The signing scheme has a hash algorithm unless it is null, and
+        // ECDAA adds a commit count
+        match read_u16(data)? {
+            TPM2_ALG_NULL => (),
+            TPM2_ALG_ECDAA => {
+                read_bytes(data, 4)?;
+            }
+            _ => {
+                read_bytes(data, 2)?;
+            }
+        }
+        Some(read_u16(data)?)
+    } else {
+        None
+    };
+    Ok(PublicArea {
+        key_type,
+        name_alg,
+        attributes,
+        curve,
+    })
+}
+
+fn read_bytes<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8]> {
+    if data.len() < len {
+        bail!("truncated");
+    }
+    let (value, rest) = data.split_at(len);
+    *data = rest;
+    Ok(value)
+}
+
+fn read_u16(data: &mut &[u8]) -> Result<u16> {
+    read_bytes(data, 2).map(|b| u16::from_be_bytes([b[0], b[1]]))
+}
+
+fn read_u32(data: &mut &[u8]) -> Result<u32> {
+    read_bytes(data, 4).map(|b| u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+}
+
 #[derive(Debug, Serialize)]
 pub struct Info {
     path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handle: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    manufacturer: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    vendor: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    firmware: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    spec_revision: Option<String>,
 }
 
 #[derive(Debug, Serialize)]
 pub struct Config {
     path: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    tcti: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    handle: Option<String>,
+    algorithm: String,
+    name_alg: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    curve: Option<String>,
+    attributes: Vec<&'static str>,
 }
 
 #[derive(Debug)]
 pub enum Test {
+    KeyAttributes(String),
     MinerKey(String),
     Sign(String),
     Ecdh(String),
@@ -82,6 +711,9 @@ pub enum Test {
 impl fmt::Display for Test {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
+            Self::KeyAttributes(key_path) => {
+                f.write_fmt(format_args!("key_attributes({key_path})"))
+            }
             Self::MinerKey(key_path) => f.write_fmt(format_args!("miner_key({key_path})")),
             Self::Sign(key_path) => f.write_fmt(format_args!("sign({key_path})")),
             Self::Ecdh(key_path) => f.write_fmt(format_args!("ecdh({key_path})")),
@@ -92,6 +724,7 @@ impl fmt::Display for Test {
 impl Test {
     pub fn run(&self) -> TestResult {
         match self {
+            Self::KeyAttributes(key_path) => check_key_attributes(key_path),
             Self::MinerKey(key_path) => check_miner_key(key_path),
             Self::Sign(key_path) => check_sign(key_path),
             Self::Ecdh(key_path) => check_ecdh(key_path),
@@ -99,6 +732,45 @@ impl Test {
     }
 }
 
+fn check<T>(name: &'static str, found: T, expected: T) -> (&'static str, test::TestOutcome)
+where
+    T: fmt::Display + PartialEq,
+{
+    let outcome = if found == expected {
+        test::pass(expected)
+    } else {
+        test::expected(expected, found)
+    };
+    (name, outcome)
+}
+
+/// Checks that the key can not leave the TPM, and can sign and do ECDH.
+fn check_key_attributes(key_path: &str) -> TestResult {
+    let public = read_public(key_path)?;
+    let attribute = |name| has_attribute(public.attributes, name);
+    let outcomes = [
+        check("algorithm", alg_name(public.key_type), "ecc".to_string()),
+        check(
+            "curve",
+            public.curve.map(curve_name).unwrap_or_default(),
+            "nist_p256".to_string(),
+        ),
+        check("fixed_tpm", attribute("fixed_tpm"), true),
+        check("fixed_parent", attribute("fixed_parent"), true),
+        check(
+            "sensitive_data_origin",
+            attribute("sensitive_data_origin"),
+            true,
+        ),
+        check("sign", attribute("sign"), true),
+        check("decrypt", attribute("decrypt"), true),
+        check("restricted", attribute("restricted"), false),
+    ]
+    .into_iter()
+    .collect::<Vec<(&'static str, test::TestOutcome)>>();
+    test::checks(outcomes).into()
+}
+
 fn check_miner_key(key_path: &str) -> TestResult {
     let keypair = tpm::Keypair::from_key_path(Network::MainNet, key_path)
         .map(helium_crypto::Keypair::from)?;
@@ -137,3 +809,205 @@ fn check_ecdh(key_path: &str) -> TestResult {
     }
     test::pass("ok").into()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The FAPI profiles installed with tpm2-tss
+    const FAPI_PROFILES: &str = "/etc/tpm2-tss/fapi-profiles/";
+    /// The TCTI of the kernel resource manager, which the device url
+    /// overrides
+    const DEFAULT_TCTI: &str = "device:/dev/tpmrm0";
+
+    /// Writes a FAPI config with a keystore in the given directory.
+    fn fapi_config(dir: &std::path::Path) -> Result<PathBuf> {
+        let config = serde_json::json!({
+            "profile_name": "P_ECCP256SHA256",
+            "profile_dir": FAPI_PROFILES,
+            "user_dir": dir.join("user"),
+            "system_dir": dir.join("system"),
+            "log_dir": dir.join("log"),
+            "tcti": DEFAULT_TCTI,
+            "system_pcrs": [],
+            "ek_cert_less": "yes",
+        });
+        let path = dir.join("fapi-config.json");
+        fs::create_dir_all(dir)?;
+        fs::write(&path, serde_json::to_vec_pretty(&config)?)?;
+        Ok(path)
+    }
+
+    #[test]
+    fn url() {
+        let device = Device::from_url(
+            &"tpm://tpm/HS/SRK/miner?handle=0x81000002&tcti=swtpm:port%3D2321"
+                .parse()
+                .expect("uri"),
+        )
+        .expect("tpm url");
+        assert_eq!(device.path, "/HS/SRK/miner");
+        assert_eq!(device.handle, Some(0x8100_0002));
+        assert_eq!(device.tcti.as_deref(), Some("swtpm:port=2321"));
+
+        let parse = |url: &str| Device::from_url(&url.parse().expect("uri"));
+        assert!(parse("tpm://localhost/HS/SRK/miner").is_err());
+        assert!(parse("tpm://tpm/HS/SRK/miner?handle=0x01000002").is_err());
+    }
+
+    /// Marshals the TPM2B_PUBLIC of a P-256 key with the given attributes,
+    /// symmetric definition and signing scheme.
+    fn ecc_public(attributes: u32, symmetric: &[u8], scheme: &[u8]) -> Vec<u8> {
+        let mut area = vec![];
+        area.extend_from_slice(&TPM2_ALG_ECC.to_be_bytes());
+        area.extend_from_slice(&[0x00, 0x0b]);
+        area.extend_from_slice(&attributes.to_be_bytes());
+        area.extend_from_slice(&[0x00, 0x00]);
+        area.extend_from_slice(symmetric);
+        area.extend_from_slice(scheme);
+        // The curve, a null kdf and the x and y coordinates
+        area.extend_from_slice(&[0x00, 0x03, 0x00, 0x10]);
+        for _ in 0..2 {
+            area.extend_from_slice(&[0x00, 0x20]);
+            area.extend_from_slice(&[0x5a; 32]);
+        }
+        let mut public = (area.len() as u16).to_be_bytes().to_vec();
+        public.extend_from_slice(&area);
+        public
+    }
+
+    #[test]
+    fn public_area() {
+        const NULL: &[u8] = &[0x00, 0x10];
+        let public = parse_public(&ecc_public(0x0006_0472, NULL, NULL)).expect("public");
+        assert_eq!(alg_name(public.key_type), "ecc");
+        assert_eq!(alg_name(public.name_alg), "sha256");
+        assert_eq!(public.curve.map(curve_name).as_deref(), Some("nist_p256"));
+        for name in [
+            "fixed_tpm",
+            "fixed_parent",
+            "sensitive_data_origin",
+            "user_with_auth",
+            "no_da",
+            "sign",
+            "decrypt",
+        ] {
+            assert!(has_attribute(public.attributes, name), "{name}");
+        }
+        assert!(!has_attribute(public.attributes, "restricted"));
+
+        // ECDSA with sha256
+        let scheme = [0x00, 0x18, 0x00, 0x0b];
+        let public = parse_public(&ecc_public(0x0006_0072, NULL, &scheme)).expect("ecdsa");
+        assert_eq!(public.curve, Some(0x0003));
+
+        // ECDAA with sha256 and a commit count
+        let scheme = [0x00, 0x1a, 0x00, 0x0b, 0x00, 0x01];
+        let public = parse_public(&ecc_public(0x0006_0072, NULL, &scheme)).expect("ecdaa");
+        assert_eq!(public.curve, Some(0x0003));
+
+        // A restricted decryption key with AES-128 in CFB mode
+        let symmetric = [0x00, 0x06, 0x00, 0x80, 0x00, 0x43];
+        let public = parse_public(&ecc_public(0x0003_0072, &symmetric, NULL)).expect("aes");
+        assert!(has_attribute(public.attributes, "restricted"));
+        assert_eq!(public.curve, Some(0x0003));
+
+        // RSA keys have no curve
+        let rsa = [
+            0x00, 0x0a, 0x00, 0x01, 0x00, 0x0b, 0x00, 0x06, 0x00, 0x72, 0x00, 0x00,
+        ];
+        let public = parse_public(&rsa).expect("rsa");
+        assert_eq!(alg_name(public.key_type), "rsa");
+        assert_eq!(public.curve, None);
+
+        let public = ecc_public(0x0006_0072, NULL, NULL);
+        assert!(parse_public(&public[..16]).is_err());
+    }
+
+    #[test]
+    fn esys_handle() {
+        // A serialized ESYS_TR starts with the handle followed by the name
+        let blob = [0x81, 0x00, 0x00, 0x02, 0x00, 0x22, 0x00, 0x0b];
+        assert_eq!(
+            parse_esys_handle(FAPI_ESYSBLOB_DESERIALIZE, &blob).expect("handle"),
+            Some(0x8100_0002)
+        );
+        // Saved contexts of keys without a handle
+        assert_eq!(parse_esys_handle(1, &blob).expect("context"), None);
+        // Transient handles are not reported
+        let blob = [0x80, 0x00, 0x00, 0x01];
+        assert_eq!(
+            parse_esys_handle(FAPI_ESYSBLOB_DESERIALIZE, &blob).expect("transient"),
+            None
+        );
+        assert!(parse_esys_handle(FAPI_ESYSBLOB_DESERIALIZE, &[0x81]).is_err());
+    }
+
+    /// Runs the device against the swtpm simulator at the TCTI given in
+    /// `GW_MFR_SWTPM_TCTI`, like `swtpm:port=2321`.
+    #[test]
+    #[ignore = "needs swtpm"]
+    fn swtpm() {
+        let tcti = env::var("GW_MFR_SWTPM_TCTI").expect("GW_MFR_SWTPM_TCTI not set");
+        let dir = env::temp_dir().join(format!("gateway_mfr-swtpm-{}", process::id()));
+        env::set_var(FAPI_CONFIG_ENV, fapi_config(&dir).expect("fapi config"));
+        let url = format!(
+            "tpm://tpm/HS/SRK/gateway-mfr?tcti={}",
+            tcti.replace('=', "%3D")
+        );
+        let device: crate::Device = url.parse().expect("tpm device");
+        device.init().expect("init");
+
+        let key = device.provision().expect("provision");
+        assert!(device.provision().is_err(), "replaced without --force");
+        assert_eq!(
+            device.load_keypair().expect("key").public_key(),
+            key.public_key()
+        );
+        let replaced = device
+            .clone()
+            .with_overwrite(Overwrite {
+                force: true,
+                backup: false,
+            })
+            .provision()
+            .expect("forced provision");
+        assert_ne!(replaced.public_key(), key.public_key());
+        assert!(
+            device
+                .clone()
+                .with_overwrite(Overwrite {
+                    force: true,
+                    backup: true,
+                })
+                .provision()
+                .is_err(),
+            "backed up a tpm key"
+        );
+
+        for test in device.get_tests() {
+            let outcome = test.run().expect("test");
+            assert!(outcome.passed(), "{test}: {outcome:?}");
+        }
+        let info = serde_json::to_value(device.get_info().expect("info")).expect("info json");
+        assert!(info.get("handle").is_none());
+
+        let persistent: crate::Device = format!(
+            "tpm://tpm/HS/SRK/gateway-mfr-persistent?handle=0x81000010&tcti={}",
+            tcti.replace('=', "%3D")
+        )
+        .parse()
+        .expect("persistent tpm device");
+        persistent.provision().expect("persistent provision");
+        let info = serde_json::to_value(persistent.get_info().expect("info")).expect("info json");
+        assert_eq!(info["handle"], "0x81000010");
+
+        // Deleting the keys also evicts the persistent handle from the TPM
+        let mut fapi = Fapi::new().expect("fapi");
+        for path in ["/HS/SRK/gateway-mfr", "/HS/SRK/gateway-mfr-persistent"] {
+            assert!(fapi.delete(path).expect("delete"), "{path} not found");
+        }
+        drop(fapi);
+        fs::remove_dir_all(dir).expect("remove swtpm dir");
+    }
+}
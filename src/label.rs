@@ -0,0 +1,162 @@
+use crate::{anyhow, Result};
+use angry_purple_tiger::AnimalName;
+use helium_crypto::PublicKey;
+use qrcode::{render::unicode, Color, QrCode};
+use serde::Serialize;
+use std::{fmt::Write, path::Path, str::FromStr};
+
+/// The public key information that is printed for a provisioned key and used
+/// to render QR codes and labels.
+#[derive(Debug, Serialize)]
+pub struct KeyLabel {
+    pub key: String,
+    pub name: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub serial: Option<String>,
+}
+
+/// Supported label template formats.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+pub enum Format {
+    /// Zebra Programming Language for Zebra label printers
+    Zpl,
+    /// Scalable vector graphics
+    Svg,
+}
+
+impl FromStr for Format {
+    type Err = crate::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "zpl" => Ok(Self::Zpl),
+            "svg" => Ok(Self::Svg),
+            _ => Err(anyhow!("unsupported label format \"{s}\"")),
+        }
+    }
+}
+
+impl Format {
+    /// Determines the label format from the extension of the given path.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        path.extension()
+            .and_then(|ext| ext.to_str())
+            .ok_or_else(|| anyhow!("missing label format for {}", path.display()))?
+            .parse()
+    }
+}
+
+impl KeyLabel {
+    pub fn new(public_key: &PublicKey, serial: Option<String>) -> Result<Self> {
+        let key = public_key.to_string();
+        Ok(Self {
+            name: key.parse::<AnimalName>()?.to_string(),
+            key,
+            serial,
+        })
+    }
+
+    /// Renders the public key as a QR code using unicode block characters,
+    /// suitable for display in a terminal.
+    pub fn to_terminal_qr(&self) -> Result<String> {
+        let code = QrCode::new(&self.key)?;
+        let qr = code
+            .render::<unicode::Dense1x2>()
+            .dark_color(unicode::Dense1x2::Light)
+            .light_color(unicode::Dense1x2::Dark)
+            .build();
+        Ok(qr)
+    }
+
+    pub fn render(&self, format: Format) -> Result<String> {
+        match format {
+            Format::Zpl => self.to_zpl(),
+            Format::Svg => self.to_svg(),
+        }
+    }
+
+    /// Renders a ZPL label with the public key as a QR code, followed by the
+    /// animal name, serial number and public key as text.
+    pub fn to_zpl(&self) -> Result<String> {
+        let mut zpl = String::new();
+        writeln!(zpl, "^XA")?;
+        writeln!(zpl, "^CI28")?;
+        writeln!(zpl, "^FO20,20^BQN,2,4^FDQA,{}^FS", zpl_escape(&self.key))?;
+        writeln!(zpl, "^FO240,40^A0N,30,30^FD{}^FS", zpl_escape(&self.name))?;
+        if let Some(serial) = &self.serial {
+            writeln!(zpl, "^FO240,90^A0N,24,24^FDS/N: {}^FS", zpl_escape(serial))?;
+        }
+        writeln!(zpl, "^FO20,240^A0N,18,18^FD{}^FS", zpl_escape(&self.key))?;
+        writeln!(zpl, "^XZ")?;
+        Ok(zpl)
+    }
+
+    /// Renders an SVG label with the public key as a QR code, followed by the
+    /// animal name, serial number and public key as text.
+    pub fn to_svg(&self) -> Result<String> {
+        const MODULE: usize = 4;
+        const QUIET: usize = 4;
+        let code = QrCode::new(&self.key)?;
+        let width = code.width();
+        let qr_size = (width + 2 * QUIET) * MODULE;
+        let text_x = qr_size + 10;
+        let label_width = text_x + 360;
+        let label_height = qr_size + 30;
+
+        let mut path = String::new();
+        for (index, color) in code.to_colors().into_iter().enumerate() {
+            if color == Color::Dark {
+                let x = (index % width + QUIET) * MODULE;
+                let y = (index / width + QUIET) * MODULE;
+                write!(path, "M{x},{y}h{MODULE}v{MODULE}h-{MODULE}z")?;
+            }
+        }
+
+        let mut svg = String::new();
+        writeln!(
+            svg,
+            r#"<svg xmlns="http://www.w3.org/2000/svg" width="{label_width}" height="{label_height}" viewBox="0 0 {label_width} {label_height}">"#
+        )?;
+        writeln!(
+            svg,
+            r##"<rect width="{label_width}" height="{label_height}" fill="#fff"/>"##
+        )?;
+        writeln!(svg, r##"<path d="{path}" fill="#000"/>"##)?;
+        writeln!(
+            svg,
+            r#"<text x="{text_x}" y="{}" font-family="sans-serif" font-size="20">{}</text>"#,
+            QUIET * MODULE + 20,
+            xml_escape(&self.name)
+        )?;
+        if let Some(serial) = &self.serial {
+            writeln!(
+                svg,
+                r#"<text x="{text_x}" y="{}" font-family="sans-serif" font-size="16">S/N: {}</text>"#,
+                QUIET * MODULE + 50,
+                xml_escape(serial)
+            )?;
+        }
+        writeln!(
+            svg,
+            r#"<text x="{}" y="{}" font-family="monospace" font-size="10">{}</text>"#,
+            QUIET * MODULE,
+            qr_size + 15,
+            xml_escape(&self.key)
+        )?;
+        writeln!(svg, "</svg>")?;
+        Ok(svg)
+    }
+}
+
+/// ZPL uses `^` and `~` as command prefixes, so they can't appear in field
+/// data.
+fn zpl_escape(s: &str) -> String {
+    s.replace(['^', '~'], "")
+}
+
+fn xml_escape(s: &str) -> String {
+    s.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
@@ -1,6 +1,8 @@
 pub mod cmd;
 pub mod device;
+pub mod label;
 pub mod result;
+pub mod settings;
 
 pub use device::Device;
 pub use result::{anyhow, bail, Error, Result};
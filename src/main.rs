@@ -1,5 +1,6 @@
 use clap::Parser;
-use gateway_mfr::{cmd, Device, Result};
+use gateway_mfr::{cmd, settings::Devices, Device, Result};
+use std::path::PathBuf;
 
 #[derive(Debug, Parser)]
 #[command(version = env!("CARGO_PKG_VERSION"))]
@@ -8,15 +9,28 @@ pub struct Cli {
     /// The security device to use.
     ///
     /// The URL for the security device is dependent on the device type being
-    /// used.
+    /// used. A device is required for all commands except `discover` and
+    /// `util`. A "@name" value refers to a named device in the devices file.
+    ///
+    /// When not given, the device is taken from the GW_MFR_DEVICE environment
+    /// variable, then the "default" entry of the devices file, and finally
+    /// "ecc://i2c-1:96?slot=0" for builds with ecc608 support.
     ///
     /// Examples:
     ///
     /// ecc608 - "ecc://i2c-1", "ecc://i2c-1:96?slot=0"
     /// file - "file:///etc/keypair.bin"\n
     /// tpm - "tpm://tpm/<key_path>"
-    #[arg(long, verbatim_doc_comment)]
-    device: Device,
+    /// keyring - "keyring://user/<description>"
+    /// exec - "exec:///usr/lib/acme/se-helper"
+    /// pkcs11 - "pkcs11:///usr/lib/softhsm/libsofthsm2.so?label=<label>"
+    /// named - "@main"
+    #[arg(long, env = "GW_MFR_DEVICE", verbatim_doc_comment)]
+    device: Option<String>,
+
+    /// The file of named devices [default: /etc/gateway_mfr/devices.toml]
+    #[arg(long, env = "GW_MFR_DEVICES")]
+    devices: Option<PathBuf>,
 
     #[command(subcommand)]
     cmd: Cmd,
@@ -28,26 +42,108 @@ pub enum Cmd {
     Key(cmd::key::Cmd),
     Provision(cmd::provision::Cmd),
     Config(cmd::config::Cmd),
+    Status(cmd::status::Cmd),
     Test(cmd::test::Cmd),
     Bench(cmd::bench::Cmd),
     Generate(cmd::generate::Cmd),
+    Url(cmd::url::Cmd),
+    Discover(cmd::discover::Cmd),
+    Doctor(cmd::doctor::Cmd),
+    Util(cmd::util::Cmd),
 }
 
 pub fn main() -> Result {
     let cli = Cli::parse();
-    cli.cmd.run(&cli.device)
+    cli.cmd.run(&cli)
+}
+
+impl Cli {
+    fn device_url(&self) -> Result<String> {
+        Devices::resolve_url(self.device.as_deref(), self.devices.as_deref())
+    }
+
+    fn device(&self) -> Result<Device> {
+        self.device_url()?.parse()
+    }
 }
 
 impl Cmd {
-    fn run(&self, device: &Device) -> Result {
+    fn run(&self, cli: &Cli) -> Result {
         match self {
-            Self::Info(cmd) => cmd.run(device),
-            Self::Key(cmd) => cmd.run(device),
-            Self::Provision(cmd) => cmd.run(device),
-            Self::Config(cmd) => cmd.run(device),
-            Self::Test(cmd) => cmd.run(device),
-            Self::Bench(cmd) => cmd.run(device),
-            Self::Generate(cmd) => cmd.run(device),
+            Self::Info(cmd) => cmd.run(&cli.device()?),
+            Self::Key(cmd) => cmd.run(&cli.device()?),
+            Self::Provision(cmd) => cmd.run(&cli.device()?),
+            Self::Config(cmd) => cmd.run(&cli.device()?),
+            Self::Status(cmd) => cmd.run(&cli.device()?),
+            Self::Test(cmd) => cmd.run(&cli.device()?),
+            Self::Bench(cmd) => cmd.run(&cli.device()?),
+            Self::Generate(cmd) => cmd.run(&cli.device()?),
+            Self::Url(cmd) => cmd.run(&cli.device()?),
+            Self::Discover(cmd) => cmd.run(),
+            Self::Doctor(cmd) => cmd.run(cli.device_url()),
+            Self::Util(cmd) => cmd.run(),
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use gateway_mfr::settings::DEFAULT_DEVICE;
+    use std::{env, fs, process};
+
+    fn device_url(args: &[&str]) -> Result<String> {
+        let args = ["gateway_mfr"].iter().chain(args).chain(&["url"]);
+        Cli::try_parse_from(args)?.device_url()
+    }
+
+    /// The device is taken from `--device`, then `GW_MFR_DEVICE`, then the
+    /// devices file and finally the built in default. The environment is
+    /// only changed in this test.
+    #[test]
+    fn device_precedence() {
+        let dir = env::temp_dir().join(format!("gateway_mfr-devices-{}", process::id()));
+        fs::create_dir_all(&dir).expect("devices dir");
+        let devices = dir.join("devices.toml");
+        fs::write(
+            &devices,
+            "default = \"@main\"\n[devices]\nmain = \"file:///main.bin\"\n",
+        )
+        .expect("devices file");
+        let invalid = dir.join("invalid.toml");
+        fs::write(&invalid, "default = [").expect("invalid devices file");
+        let devices = devices.to_str().expect("devices path");
+        let invalid = invalid.to_str().expect("invalid path");
+        env::remove_var("GW_MFR_DEVICE");
+        env::remove_var("GW_MFR_DEVICES");
+
+        assert_eq!(device_url(&[]).ok().as_deref(), DEFAULT_DEVICE);
+        assert_eq!(
+            device_url(&["--devices", devices]).expect("devices file"),
+            "file:///main.bin"
+        );
+        env::set_var("GW_MFR_DEVICE", "file:///env.bin");
+        assert_eq!(
+            device_url(&["--devices", devices]).expect("environment"),
+            "file:///env.bin"
+        );
+        assert_eq!(
+            device_url(&["--devices", devices, "--device", "file:///flag.bin"]).expect("flag"),
+            "file:///flag.bin"
+        );
+        assert_eq!(
+            device_url(&["--devices", devices, "--device", "@main"]).expect("named"),
+            "file:///main.bin"
+        );
+
+        // The devices file is only read for a named or default device
+        assert_eq!(
+            device_url(&["--devices", invalid, "--device", "file:///flag.bin"]).expect("flag"),
+            "file:///flag.bin"
+        );
+        assert!(device_url(&["--devices", invalid, "--device", "@main"]).is_err());
+        env::remove_var("GW_MFR_DEVICE");
+        assert!(device_url(&["--devices", invalid]).is_err());
+        fs::remove_dir_all(dir).expect("remove devices dir");
+    }
+}
@@ -0,0 +1,97 @@
+use crate::{anyhow, bail, Result};
+use serde::Deserialize;
+use std::{
+    collections::HashMap,
+    path::{Path, PathBuf},
+};
+
+/// The device used when none is given on the command line, in the
+/// environment or in the devices file.
+#[cfg(feature = "ecc608")]
+pub const DEFAULT_DEVICE: Option<&str> = Some("ecc://i2c-1:96?slot=0");
+#[cfg(not(feature = "ecc608"))]
+pub const DEFAULT_DEVICE: Option<&str> = None;
+
+/// The devices file that is read when no other path is given.
+pub const DEFAULT_DEVICES_PATH: &str = "/etc/gateway_mfr/devices.toml";
+
+/// A file of named device URLs, for example:
+///
+/// ```toml
+/// default = "@main"
+///
+/// [devices]
+/// main = "ecc://i2c-1:96?slot=0"
+/// lab = "file:///var/lib/gateway_mfr/keypair.bin"
+/// ```
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Devices {
+    /// The device to use when `--device` and `GW_MFR_DEVICE` are not set.
+    /// Either a device URL or a `@name` reference to an entry in `devices`.
+    pub default: Option<String>,
+    #[serde(default)]
+    pub devices: HashMap<String, String>,
+    #[serde(skip)]
+    path: PathBuf,
+}
+
+impl Devices {
+    /// Loads the devices file at the given path. When no path is given the
+    /// default path is used, and a missing default file is treated as empty.
+    pub fn load(path: Option<&Path>) -> Result<Self> {
+        let (path, required) = match path {
+            Some(path) => (path, true),
+            None => (Path::new(DEFAULT_DEVICES_PATH), false),
+        };
+        if !required && !path.exists() {
+            return Ok(Self::default());
+        }
+        let contents = std::fs::read_to_string(path)
+            .map_err(|err| anyhow!("failed to read devices file {}: {err}", path.display()))?;
+        let mut devices: Self = toml::from_str(&contents)
+            .map_err(|err| anyhow!("invalid devices file {}: {err}", path.display()))?;
+        devices.path = path.to_path_buf();
+        Ok(devices)
+    }
+
+    /// Resolves the url of the device to use. In order of precedence this is
+    /// the given device (from `--device` or `GW_MFR_DEVICE`), the `default`
+    /// entry of the devices file, and finally the compile time default. The
+    /// devices file at the given path is only read when no device is given
+    /// or to look up a `@name` reference.
+    pub fn resolve_url(device: Option<&str>, path: Option<&Path>) -> Result<String> {
+        let mut devices = None;
+        let url = match device {
+            Some(url) => url.to_string(),
+            None => devices
+                .insert(Self::load(path)?)
+                .default
+                .as_deref()
+                .or(DEFAULT_DEVICE)
+                .ok_or_else(|| anyhow!("a security device is required, use --device"))?
+                .to_string(),
+        };
+        let Some(name) = url.strip_prefix('@') else {
+            return Ok(url);
+        };
+        let devices = match devices {
+            Some(devices) => devices,
+            None => Self::load(path)?,
+        };
+        devices.lookup(name).map(str::to_string)
+    }
+
+    fn lookup(&self, name: &str) -> Result<&str> {
+        let Some(url) = self.devices.get(name) else {
+            if self.path.as_os_str().is_empty() {
+                bail!("unknown device @{name}, no devices file found at {DEFAULT_DEVICES_PATH}");
+            }
+            bail!("unknown device @{name} in {}", self.path.display());
+        };
+        if url.starts_with('@') {
+            bail!("device @{name} must be a device url, not a reference");
+        }
+        Ok(url)
+    }
+}